@@ -1,32 +1,215 @@
 #![no_std]
 
 pub use heapless::VecView;
-pub use iso7816::{command::CommandView, Interface};
+pub use iso7816::{command::CommandView, Aid, Interface, Status};
 
-pub type Result = iso7816::Result<()>;
+pub type Result = iso7816::Result<Reply>;
+
+/// Outcome of an app handling a command.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Reply {
+    /// The response data written to `reply` is complete.
+    #[default]
+    Ready,
+    /// The response is deferred. The dispatcher will call [`App::poll`] until it is ready, or
+    /// [`App::cancel`] if the command is canceled.
+    Pending,
+    /// The response data written to `reply` is complete, and is sent with the given status
+    /// instead of `9000`.
+    ///
+    /// ISO 7816-4 only allows response data with warning statuses (`62XX` and `63XX`): the
+    /// dispatcher drops the data sent with other statuses.  The chunks of a streamed response
+    /// that were already retrieved can not be dropped.
+    Status(Status),
+    /// The response data written to `reply` is only the beginning of the response.  The
+    /// dispatcher will call [`App::next_chunk`] for the rest, as the reader retrieves it.
+    More,
+}
+
+/// Response requested by a SELECT command, from bits 4 and 3 of P2 (ISO 7816-4 11.2.2).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResponseFormat {
+    /// Return the file control information (FCI) template.
+    Fci,
+    /// Return the file control parameters (FCP) template.
+    Fcp,
+    /// Return the file management data (FMD) template.
+    Fmd,
+    /// Return no response data.  The dispatcher drops the data of an app's reply to a SELECT
+    /// by AID asking for this.
+    NoData,
+}
+
+impl ResponseFormat {
+    /// Decode the response format from P2 of a SELECT command.
+    pub const fn from_p2(p2: u8) -> Self {
+        match (p2 >> 2) & 0b11 {
+            0b00 => Self::Fci,
+            0b01 => Self::Fcp,
+            0b10 => Self::Fmd,
+            _ => Self::NoData,
+        }
+    }
+}
+
+/// Information about the command an app is called for, filled in by the dispatcher.
+///
+/// Fields may be added in future versions: apps read them, and a `Context` is created with
+/// [`Context::new`] before setting them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Context {
+    /// Interface the command was received on.
+    pub interface: Interface,
+    /// Logical channel (ISO 7816-4 5.4.1) the command was received on, `0` being the basic
+    /// channel.  An app can be selected on several channels at once, and should keep its state
+    /// per channel.
+    pub channel: u8,
+    /// Whether the command was received as a chain of commands (ISO 7816-4 5.3.3).
+    pub chained: bool,
+    /// Le field of the command: the maximum length of the response data expected by the reader.
+    pub le: usize,
+    /// Whether the command used the extended length encoding.
+    pub extended: bool,
+    /// Maximum length of a response frame on the interface, including SW1SW2.  Longer responses
+    /// are retrieved with GET RESPONSE.
+    pub max_frame_size: usize,
+    /// Identifier of the session on the interface, which changes every time the session is reset.
+    pub session: u32,
+    /// AID the app was selected with on the channel: its [`aid`](iso7816::App::aid) or one of
+    /// its [`aliases`](App::aliases).  For a command routed to the app by the dispatcher whatever
+    /// app is selected, the AID of the route.
+    pub aid: Option<Aid>,
+    /// Response requested by the command, if it is a SELECT.
+    pub response_format: Option<ResponseFormat>,
+    /// Whether the command is a SELECT built by the dispatcher to select the default app of the
+    /// interface at the start of a session, instead of a command of the reader.
+    pub implicit: bool,
+}
+
+impl Context {
+    pub const fn new(interface: Interface) -> Self {
+        Self {
+            interface,
+            channel: 0,
+            chained: false,
+            le: 0,
+            extended: false,
+            max_frame_size: 0,
+            session: 0,
+            aid: None,
+            response_format: None,
+            implicit: false,
+        }
+    }
+}
+
+/// Why an app is deselected from a logical channel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeselectReason {
+    /// Another app was selected on the channel.
+    AppSelected,
+    /// The channel was closed with MANAGE CHANNEL.
+    ChannelClosed,
+    /// The session ended with a reset of the card, for example a CCID warm reset.
+    Reset,
+    /// The interface lost power, for example the NFC field dropped or the CCID slot was powered
+    /// off.
+    PowerDown,
+    /// The session was taken over by another interface, whose command waited for the end of the
+    /// current transaction.  The card was not reset, so [`App::reset`] is not called.
+    InterfaceLost,
+}
 
 /// An App can receive and respond APDUs at behest of the ApduDispatch.
+///
+/// The [`Context`] passed with each command tells on which interface and logical channel it was
+/// received, and how.
 pub trait App: iso7816::App {
+    /// Other AIDs the app can be selected with, in addition to its
+    /// [`aid`](iso7816::App::aid).  The AID used for the SELECT is given in [`Context::aid`].
+    fn aliases(&self) -> &[Aid] {
+        &[]
+    }
+
     /// Given parsed APDU for select command.
     /// Write response data back to buf, and return length of payload.  Return APDU Error code on error.
-    /// Alternatively, the app can defer the response until later by returning it in `poll()`.
+    /// Alternatively, the app can defer the response until later by returning [`Reply::Pending`]
+    /// and providing it in `poll()`.
+    ///
+    /// If the app returns an error, it is not selected, and the app selected before on the
+    /// channel stays selected without being deselected.
+    ///
+    /// The default app of an interface is selected implicitly at the start of each session on
+    /// the basic channel, with a SELECT by its AID asking for no response data and
+    /// [`Context::implicit`] set.  The app has to reply to it immediately: if it returns
+    /// [`Reply::Pending`] or an error, no app is selected.  A deferred implicit selection is
+    /// [canceled](Self::cancel).
     fn select(
         &mut self,
-        interface: Interface,
+        context: &Context,
         apdu: CommandView<'_>,
         reply: &mut VecView<u8>,
     ) -> Result;
 
-    /// Deselects the app. This is the result of another app getting selected.
+    /// Deselects the app on the given logical channel, for the given reason.
     /// App should clear any sensitive state and reset security indicators.
-    fn deselect(&mut self);
+    fn deselect(&mut self, channel: u8, reason: DeselectReason);
 
     /// Given parsed APDU for app when selected.
     /// Write response data back to buf, and return length of payload.  Return APDU Error code on error.
-    fn call(
-        &mut self,
-        interface: Interface,
-        apdu: CommandView<'_>,
-        reply: &mut VecView<u8>,
-    ) -> Result;
+    /// Alternatively, the app can defer the response until later by returning [`Reply::Pending`]
+    /// and providing it in `poll()`.
+    fn call(&mut self, context: &Context, apdu: CommandView<'_>, reply: &mut VecView<u8>)
+        -> Result;
+
+    /// Called repeatedly after `select` or `call` returned [`Reply::Pending`], until it returns
+    /// something else.
+    /// Write the deferred response data back to buf, like in `call`.  `context` is the one of the
+    /// deferred command.
+    ///
+    /// Apps that never defer their responses do not need to implement this method.
+    fn poll(&mut self, context: &Context, reply: &mut VecView<u8>) -> Result {
+        let _ = (context, reply);
+        Err(Status::UnspecifiedNonpersistentExecutionError)
+    }
+
+    /// Called instead of `poll` when the command whose response the app deferred is canceled:
+    /// by the reader, for example with a CCID abort, or because the app deferred the implicit
+    /// selection of the default app.  `context` is the one of the deferred command.
+    /// The app is not polled for this command anymore, and no response is sent for it.  It should
+    /// stop waiting for the response, for example for user presence.  A canceled SELECT does not
+    /// select the app.
+    fn cancel(&mut self, context: &Context) {
+        let _ = context;
+    }
+
+    /// Called after `select`, `call`, `poll` or `next_chunk` returned [`Reply::More`], to
+    /// continue the response.
+    /// Write the next part of the response data back to buf, and return [`Reply::More`] if data
+    /// remains after it, or [`Reply::Ready`] or [`Reply::Status`] if this is the end of the
+    /// response.  The response can be longer than buf in total.  `context` is the one of the
+    /// command the next part is sent in response to: the original command or a GET RESPONSE.
+    ///
+    /// If the reader sends another command before retrieving the whole response, the rest of it
+    /// is dropped without calling this method again.
+    ///
+    /// Apps that never stream their responses do not need to implement this method.
+    fn next_chunk(&mut self, context: &Context, reply: &mut VecView<u8>) -> Result {
+        let _ = (context, reply);
+        Err(Status::UnspecifiedNonpersistentExecutionError)
+    }
+
+    /// Called on every app when the card is reset on the given interface, after the apps
+    /// selected on it have been deselected with [`DeselectReason::Reset`].
+    /// App should clear any state kept for the session on that interface.
+    fn reset(&mut self, interface: Interface) {
+        let _ = interface;
+    }
+
+    /// Called on every app when the given interface loses power, after the apps selected on it
+    /// have been deselected with [`DeselectReason::PowerDown`].
+    fn power_down(&mut self, interface: Interface) {
+        let _ = interface;
+    }
 }
@@ -3,148 +3,899 @@
 //! and will gauruntee only one app will be selected at a time.  Only the selected app will
 //! receive APDU's.  Apps are selected based on their AID.
 //!
-//! Additionally, the APDU dispatch could repeatedly call "poll" on the selected App.  If this was in place, the App
-//! could choose to reply at time of APDU, or can defer and reply later (during one of the poll calls).
+//! Additionally, the APDU dispatch repeatedly calls "poll" on the App handling the current command.  The App
+//! can choose to reply at time of APDU, or can defer and reply later (during one of the poll calls).
+//!
+//! Responses that do not fit in the response buffer can be streamed: the App returns the first part
+//! and the dispatch reads the rest from the App as the reader retrieves it with GET RESPONSE.
 //!
 //! Apps need to implement the App trait to be managed.
 //!
+//! The dispatch supports the logical channels of ISO 7816-4 5.4.1: each channel has its own
+//! selected app, and channels are opened and closed with the MANAGE CHANNEL command.
+//!
+//! A default app can be set for each interface with [`Routing::with_default_app`]: it is
+//! selected implicitly at the start of each session on the interface.
+//!
+//! Commands sent to a logical channel without a selected app are passed to the [`Fallback`]
+//! handler set with [`Routing::with_fallback`], if any.  Commands matching one of the [`Route`]s
+//! set with [`Routing::with_routes`] go to the app or handler of the route instead of the
+//! selected app.  The [`Routing`] is given to [`ApduDispatch::with_routing`], or to each call of
+//! [`Session::process`].
+//!
+//! A SELECT with a partial AID selects the first app with a matching AID, and SELECTs with the
+//! "next occurrence" P2 the following ones, in the order of the apps and their aliases.
+//!
+//! Commands are received on [`Port`]s: by default the contact and contactless interchanges, or any
+//! number of [transports](crate::transport) given to [`ApduDispatch::with_ports`].
+//!
+//! By default, the first interface used is bound to the dispatch until it is [reset](ApduDispatch::reset),
+//! and requests from the other interface are handled according to the [`ConflictPolicy`].
+//! Alternatively, the dispatch can be created with [`ApduDispatch::new_concurrent`], which keeps a
+//! separate [`Session`] for each interface.
+//!
+//! A [`Session`] holds the command and response buffers.  With [`ApduDispatch::new_in`] and
+//! [`ApduDispatch::new_concurrent_in`], they are provided by the caller, for example in a
+//! `static`, so that neither the dispatch nor [`ApduDispatch::poll`] need much stack.
+//!
+//! Transports that do not use interchanges can call [`Session::process`] directly: it handles a
+//! command and writes its response with the same logic as [`ApduDispatch`], which wraps it.
+//! Deferred responses are then polled with [`Session::poll`].
+//!
+
+use core::borrow::BorrowMut;
 
-use crate::response::SIZE as ResponseSize;
+use crate::app::{self, Context, DeselectReason, Reply, ResponseFormat};
 use crate::App;
 use crate::{
-    interchanges::{self, Responder},
-    response, Command,
+    command,
+    interchanges::Responder,
+    response,
+    transport::{Port, Transport},
 };
 
 use iso7816::{
-    command::{CommandView, FromSliceError},
+    command::{
+        class::{Class, Interindustry, Range},
+        CommandView, FromSliceError,
+    },
     Aid, Instruction, Result, Status,
 };
 
-/// Maximum length of a data field of a response that can fit in an interchange message after
-/// concatenation of SW1SW2
-const MAX_INTERCHANGE_DATA: usize = if interchanges::SIZE < ResponseSize {
-    interchanges::SIZE
-} else {
-    ResponseSize
-} - 2;
+/// Length of the header and length fields of a command APDU in the extended length encoding
+const COMMAND_OVERHEAD: usize = 9;
+
+/// Default size of the command buffer of a [`Session`], which holds commands with up to
+/// [`command::SIZE`] bytes of data
+pub const COMMAND_BUFFER_SIZE: usize = command::SIZE + COMMAND_OVERHEAD;
+
+/// Number of logical channels: 4 in the first interindustry class range and 16 more in the
+/// further interindustry class range
+pub const LOGICAL_CHANNELS: usize = 20;
+
+/// Instruction byte of the MANAGE CHANNEL command
+const MANAGE_CHANNEL: u8 = 0x70;
 
 pub use iso7816::Interface;
 
+/// How to handle requests from an interface while the dispatch is bound to the other one
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// Reply with `UnspecifiedNonpersistentExecutionError` until the bound interface is reset.
+    #[default]
+    Reject,
+    /// Keep the request in the interchange until the transaction on the bound interface is
+    /// finished, i.e. no command chain is being received, no response is being sent with GET
+    /// RESPONSE and no app is processing a request.  The session of the bound interface is then
+    /// closed, deselecting its apps with [`DeselectReason::InterfaceLost`], and the request is
+    /// processed.
+    Wait,
+}
+
+/// Order in which the ports with a command are served
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SchedulingPolicy {
+    /// Serve the port with the highest priority, the contactless one by default.
+    #[default]
+    Priority,
+    /// Serve the ports in turn, in the order of their priorities, starting after the port
+    /// served last.
+    RoundRobin,
+    /// Serve the port served last first, then the others by priority.
+    LastServedFirst,
+}
+
+/// What happened while handling a command, reported by [`ApduDispatch::poll_events`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Event {
+    /// The session of the interface was bound to it
+    Bound(Interface),
+    /// The session of the interface was released
+    Released(Interface),
+    /// An app was selected on a logical channel
+    Selected {
+        interface: Interface,
+        channel: u8,
+        aid: Aid,
+    },
+    /// An app was deselected from a logical channel
+    Deselected {
+        interface: Interface,
+        channel: u8,
+        aid: Aid,
+        reason: DeselectReason,
+    },
+    /// A command was passed to the app selected on a logical channel
+    Dispatched {
+        interface: Interface,
+        channel: u8,
+        aid: Aid,
+    },
+    /// A command was passed to the [fallback handler](Fallback)
+    DispatchedToFallback { interface: Interface, channel: u8 },
+    /// A command of a chain was buffered
+    ChainBuffered(Interface),
+    /// A chunk of a buffered or streamed response was sent in reply to GET RESPONSE
+    ResponseServed(Interface),
+    /// A command was answered with the error `status` by the dispatch, for example because it was
+    /// malformed or unexpected, its logical channel was not open, or no app or fallback handler
+    /// handled it
+    Rejected {
+        interface: Interface,
+        status: Status,
+    },
+}
+
+/// Handler of the commands sent to a logical channel without a selected app.
+///
+/// It serves the card-level commands that readers send without selecting an app, for example
+/// GET DATA for the CPLC, IIN or CIN, GET CHALLENGE, or vendor specific commands.  It is set with
+/// [`Routing::with_fallback`].
+///
+/// Both interfaces of a [concurrent](ApduDispatch::new_concurrent) dispatch share the handler:
+/// while it defers or streams a response to one interface, the commands for it from the other
+/// interface are rejected with `UnspecifiedNonpersistentExecutionError`.
+pub trait Fallback {
+    /// Handle a command like [`App::call`], writing the response data to `reply`.
+    ///
+    /// Returns `None` if the command is not handled, in which case it is answered with the
+    /// [fallback status](Routing::with_fallback_status).
+    fn call(
+        &mut self,
+        context: &Context,
+        apdu: CommandView<'_>,
+        reply: &mut app::VecView<u8>,
+    ) -> Option<app::Result>;
+
+    /// Poll for the response to a command deferred with [`Reply::Pending`], like [`App::poll`].
+    fn poll(&mut self, context: &Context, reply: &mut app::VecView<u8>) -> app::Result {
+        let _ = (context, reply);
+        Err(Status::UnspecifiedNonpersistentExecutionError)
+    }
+
+    /// Stop waiting for the response to a command deferred with [`Reply::Pending`], like
+    /// [`App::cancel`].
+    fn cancel(&mut self, context: &Context) {
+        let _ = context;
+    }
+
+    /// Write the next chunk of a response streamed with [`Reply::More`], like
+    /// [`App::next_chunk`].
+    fn next_chunk(&mut self, context: &Context, reply: &mut app::VecView<u8>) -> app::Result {
+        let _ = (context, reply);
+        Err(Status::UnspecifiedNonpersistentExecutionError)
+    }
+}
+
+/// Destination of the commands matching a [`Route`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RouteTarget {
+    /// The app with the AID, which does not need to be selected.  The AID is given to the app in
+    /// [`Context::aid`].  While the app is in use by the other interface used concurrently,
+    /// selected or deferring or streaming a response, the commands are rejected.
+    App(Aid),
+    /// The [fallback handler](Routing::with_fallback)
+    Fallback,
+}
+
+/// Route of the commands with a given class and instruction to an app or to the fallback
+/// handler, whatever app is selected on their logical channel.  The logical channel must be open.
+///
+/// By default, a route matches the commands with exactly the given class byte, received on any
+/// interface.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Route {
+    class: u8,
+    class_mask: u8,
+    instruction: u8,
+    interface: Option<Interface>,
+    target: RouteTarget,
+}
+
+impl Route {
+    pub const fn new(class: u8, instruction: u8, target: RouteTarget) -> Self {
+        Self {
+            class,
+            class_mask: 0xFF,
+            instruction,
+            interface: None,
+            target,
+        }
+    }
+
+    /// Only compare the bits of the class byte that are set in `mask`, for example `0xFC` to
+    /// match the commands of all the logical channels of the first interindustry range.
+    pub const fn with_class_mask(mut self, mask: u8) -> Self {
+        self.class_mask = mask;
+        self
+    }
+
+    /// Only match the commands received on `interface`.
+    pub const fn with_interface(mut self, interface: Interface) -> Self {
+        self.interface = Some(interface);
+        self
+    }
+
+    pub fn target(&self) -> RouteTarget {
+        self.target
+    }
+
+    fn matches(&self, interface: Interface, apdu: &CommandView<'_>) -> bool {
+        apdu.class().into_inner() & self.class_mask == self.class & self.class_mask
+            && u8::from(apdu.instruction()) == self.instruction
+            && self.interface.is_none_or(|route| route == interface)
+    }
+}
+
+/// Handling of the commands that do not go to the app selected on their logical channel: the
+/// default apps, the [`Route`]s and the [`Fallback`] handler.
+pub struct Routing<'a> {
+    /// Default app of the contact and of the contactless interface
+    default_apps: [Option<Aid>; 2],
+    routes: &'a [Route],
+    fallback: Option<&'a mut dyn Fallback>,
+    /// Status of the commands sent to a channel without a selected app that are not handled by
+    /// the fallback handler
+    fallback_status: Status,
+}
+
+impl<'a> Routing<'a> {
+    pub const fn new() -> Self {
+        Self {
+            default_apps: [None; 2],
+            routes: &[],
+            fallback: None,
+            fallback_status: Status::NotFound,
+        }
+    }
+
+    /// Select the app with `aid` implicitly at the start of each session on `interface`, before
+    /// handling its first command.
+    ///
+    /// A session starts with its first command, and again after it is
+    /// [reset](ApduDispatch::reset) or [powered down](ApduDispatch::power_down).
+    pub fn with_default_app(mut self, interface: Interface, aid: Aid) -> Self {
+        self.default_apps[interface_index(interface)] = Some(aid);
+        self
+    }
+
+    /// Send the commands matching one of `routes` to its target instead of the app selected on
+    /// their logical channel.  The first matching route is used.
+    pub fn with_routes(mut self, routes: &'a [Route]) -> Self {
+        self.routes = routes;
+        self
+    }
+
+    /// Let `fallback` handle the commands sent to a logical channel without a selected app.
+    pub fn with_fallback(mut self, fallback: &'a mut dyn Fallback) -> Self {
+        self.fallback = Some(fallback);
+        self
+    }
+
+    /// Set the status of the commands sent to a logical channel without a selected app that are
+    /// not handled by the [fallback handler](Self::with_fallback), `NotFound` by default.
+    pub fn with_fallback_status(mut self, status: Status) -> Self {
+        self.fallback_status = status;
+        self
+    }
+
+    fn default_app(&self, interface: Interface) -> Option<Aid> {
+        self.default_apps[interface_index(interface)]
+    }
+}
+
+impl Default for Routing<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Error returned by [`ApduDispatch::poll`] and [`Session::process`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The response to a command received on the interface could not be sent by its transport
+    Transport(Interface),
+    /// The buffers of the session of the interface were in an unexpected state.  The command was
+    /// answered with `UnspecifiedCheckingError`.
+    UnexpectedState(Interface),
+}
+
 pub enum RequestType {
-    Select(Aid, Interface),
-    /// Get Response including the Le field of the command
-    GetResponse,
-    NewCommand(Interface),
+    Select(Aid, Interface, u8),
+    /// Get Response including the Le field of the command, and the logical channel it was sent on
+    GetResponse(Interface, u8),
+    NewCommand(Interface, u8),
+    /// MANAGE CHANNEL, including the logical channel it was sent on
+    ManageChannel(Interface, u8),
     /// Incorrect command, which means an error should be returned
-    BadCommand(Status),
+    BadCommand(Interface, Status),
     None,
 }
 
-#[derive(PartialEq)]
+#[derive(Clone, Copy, PartialEq)]
 enum RawApduBuffer {
     None,
-    Request(Command),
-    Response(response::Data),
+    /// Command in the command buffer
+    Request,
+    /// Response in the reply buffer, and what follows it
+    Response(Tail),
+}
+
+/// End of a buffered response
+#[derive(Clone, Copy, PartialEq)]
+enum Tail {
+    /// Status to send with the last chunk
+    Status(Status),
+    /// The rest of the response is read from the app with `App::next_chunk`
+    Stream(Target),
+}
+
+/// Command APDU, stored in the extended length encoding so that it can be built in place and
+/// viewed without copying it.
+///
+/// The data field always starts at offset 7, after the header and `00 Lc1 Lc2`.  Without data,
+/// these three bytes are `00 Le1 Le2` or absent.
+struct CommandBuffer<const C: usize> {
+    apdu: heapless::Vec<u8, C>,
+    lc: usize,
+    le: usize,
+}
+
+impl<const C: usize> CommandBuffer<C> {
+    /// Evaluated at compile time by [`CommandBuffer::new`]
+    const VALID: () = assert!(C >= COMMAND_OVERHEAD, "command buffer too small");
+
+    const fn new() -> Self {
+        let () = Self::VALID;
+        Self {
+            apdu: heapless::Vec::new(),
+            lc: 0,
+            le: 0,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.apdu.clear();
+        self.lc = 0;
+        self.le = 0;
+    }
+
+    /// Append a command of a chain, taking the header and Le of the last one, like
+    /// [`Command::extend_from_command_view`](iso7816::Command::extend_from_command_view)
+    fn extend(&mut self, command: CommandView<'_>) {
+        // Keep the data field, the lengths are encoded again
+        self.apdu.resize(7 + self.lc, 0).ok();
+        self.apdu[..5].copy_from_slice(&[
+            command.class().into_inner(),
+            command.instruction().into(),
+            command.p1,
+            command.p2,
+            0,
+        ]);
+        let lc = self.lc + command.data().len();
+        if lc <= usize::from(u16::MAX) && self.apdu.extend_from_slice(command.data()).is_ok() {
+            self.lc = lc;
+        } else {
+            info!("command too long, dropping {} bytes", command.data().len());
+        }
+        self.le = command.expected();
+
+        // Le = 65536 is encoded as 0000
+        let le = (self.le as u16).to_be_bytes();
+        match (self.lc, self.le) {
+            (0, 0) => self.apdu.truncate(4),
+            (0, _) => self.apdu[5..7].copy_from_slice(&le),
+            (lc, _) => {
+                self.apdu[5..7].copy_from_slice(&(lc as u16).to_be_bytes());
+                if self.le != 0 {
+                    self.apdu.extend_from_slice(&le).ok();
+                }
+            }
+        }
+    }
+
+    fn view(&self) -> Result<CommandView<'_>> {
+        CommandView::try_from(self.apdu.as_slice()).map_err(|_| {
+            info!("Malformed command buffer");
+            Status::UnspecifiedCheckingError
+        })
+    }
 }
 
-struct ApduBuffer {
+struct ApduBuffer<const C: usize, const R: usize> {
     pub raw: RawApduBuffer,
+    command: CommandBuffer<C>,
+    /// Response data, written by the apps and sent from `offset` on
+    reply: response::Data<R>,
+    offset: usize,
+    /// Logical channel of the buffered command, and of its response
+    channel: u8,
 }
 
-impl ApduBuffer {
+impl<const C: usize, const R: usize> ApduBuffer<C, R> {
+    const fn new() -> Self {
+        Self {
+            raw: RawApduBuffer::None,
+            command: CommandBuffer::new(),
+            reply: response::Data::new(),
+            offset: 0,
+            channel: 0,
+        }
+    }
+
     fn request(&mut self, command: CommandView<'_>) {
-        match &mut self.raw {
-            RawApduBuffer::Request(buffered) => {
-                buffered.extend_from_command_view(command).ok();
+        if self.raw != RawApduBuffer::Request {
+            if self.raw != RawApduBuffer::None {
+                info!(
+                    "Was buffering the last response, but aborting that now for this new request."
+                );
             }
+            self.raw = RawApduBuffer::Request;
+            self.command.clear();
+            // A new command starts with an empty response
+            self.reply.clear();
+            self.offset = 0;
+            self.channel = logical_channel(command.class());
+        }
+        self.command.extend(command);
+    }
+
+    /// Send the data in the reply buffer as response
+    fn response(&mut self, tail: Tail) {
+        self.raw = RawApduBuffer::Response(tail);
+        self.offset = 0;
+    }
+
+    /// Response data that was not sent yet
+    fn remaining(&self) -> &[u8] {
+        self.reply.get(self.offset..).unwrap_or_default()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum LogicalChannel {
+    Closed,
+    /// Open, with the AID of the selected app if any
+    Open(Option<Aid>),
+}
+
+/// Logical channel number encoded in the class byte.
+///
+/// The proprietary classes used by GlobalPlatform, `80` to `83` and `C0` to `CF`, are decoded
+/// like the interindustry ones.  The commands with other classes are sent to the basic channel.
+fn logical_channel(class: Class) -> u8 {
+    let cla = class.into_inner();
+    match (class.range(), cla) {
+        (Range::Interindustry(Interindustry::First), _) | (Range::Proprietary, 0x80..=0x83) => {
+            cla & 0b11
+        }
+        (Range::Interindustry(Interindustry::Further), _) | (Range::Proprietary, 0xC0..=0xCF) => {
+            4 + (cla & 0x0f)
+        }
+        _ => 0,
+    }
+}
+
+/// Which of the apps matching the AID of a SELECT is selected (ISO 7816-4 11.2.2)
+#[derive(Clone, Copy, PartialEq)]
+enum Occurrence {
+    /// The first app, in the order of the apps and their AIDs
+    First,
+    /// The app after the one selected on the channel
+    Next,
+}
+
+impl Occurrence {
+    /// Decode the occurrence from P2 of a SELECT.  The last and previous occurrences are not
+    /// supported.
+    fn from_p2(p2: u8) -> Result<Self> {
+        match p2 & 0b11 {
+            0b00 => Ok(Self::First),
+            0b10 => Ok(Self::Next),
             _ => {
-                if self.raw != RawApduBuffer::None {
-                    info!("Was buffering the last response, but aborting that now for this new request.");
-                }
-                let mut new_cmd = iso7816::Command::try_from(&[0, 0, 0, 0]).unwrap();
-                new_cmd.extend_from_command_view(command).ok();
-                self.raw = RawApduBuffer::Request(new_cmd);
+                info!("unsupported occurrence {}", p2 & 0b11);
+                Err(Status::IncorrectP1OrP2Parameter)
             }
         }
     }
+}
 
-    fn response(&mut self, response: &response::Data) {
-        self.raw = RawApduBuffer::Response(response.clone());
-    }
+/// App, or fallback handler if `aid` is `None`, and logical channel that a command was sent to
+#[derive(Clone, Copy, PartialEq)]
+struct Target {
+    aid: Option<Aid>,
+    channel: u8,
 }
 
-pub struct ApduDispatch<'pipe> {
-    // or currently_selected_aid, or...
-    current_aid: Option<Aid>,
-    contact: Responder<'pipe>,
-    contactless: Responder<'pipe>,
-    interface: Option<Interface>,
+/// App or fallback handler that deferred or streams a response
+enum Handler<'a> {
+    App(&'a mut dyn App),
+    Fallback(&'a mut dyn Fallback),
+}
+
+impl<'a> Handler<'a> {
+    /// Handler of the responses of `target`
+    fn find(
+        target: Target,
+        apps: &'a mut [&mut dyn App],
+        routing: &'a mut Routing<'_>,
+    ) -> Option<Self> {
+        match target.aid {
+            Some(aid) => find_app(Some(&aid), apps).map(|app| Self::App(&mut **app)),
+            None => {
+                let fallback = routing.fallback.as_deref_mut()?;
+                Some(Self::Fallback(fallback))
+            }
+        }
+    }
+
+    fn poll(&mut self, context: &Context, reply: &mut app::VecView<u8>) -> app::Result {
+        match self {
+            Self::App(app) => app.poll(context, reply),
+            Self::Fallback(fallback) => fallback.poll(context, reply),
+        }
+    }
+
+    fn next_chunk(&mut self, context: &Context, reply: &mut app::VecView<u8>) -> app::Result {
+        match self {
+            Self::App(app) => app.next_chunk(context, reply),
+            Self::Fallback(fallback) => fallback.next_chunk(context, reply),
+        }
+    }
 
-    buffer: ApduBuffer,
+    fn cancel(&mut self, context: &Context) {
+        match self {
+            Self::App(app) => app.cancel(context),
+            Self::Fallback(fallback) => fallback.cancel(context),
+        }
+    }
+}
+
+/// Selection state and command and response buffers of the session on an interface.
+///
+/// The command buffer holds `C` bytes, enough for a command with `C - 9` bytes of data, after
+/// reassembly of chained commands.  The response buffer holds `R` bytes, which is the longest
+/// response an app can return without streaming it.
+///
+/// A session can also be used without interchanges: [`Session::process`] handles a command and
+/// returns its response directly, with the same chaining, SELECT, MANAGE CHANNEL and GET RESPONSE
+/// handling as [`ApduDispatch`].
+pub struct Session<const C: usize = COMMAND_BUFFER_SIZE, const R: usize = { response::SIZE }> {
+    /// Selected app of each logical channel, indexed by channel number
+    channels: [LogicalChannel; LOGICAL_CHANNELS],
+    buffer: ApduBuffer<C, R>,
     response_len_expected: usize,
+    /// Whether the last received command used the extended length encoding
+    extended: bool,
     was_request_chained: bool,
+    /// Identifier of the session, changed when it is reset
+    id: u32,
+    /// Command whose response was deferred by the app
+    pending: Option<Target>,
+    /// If the pending command is a SELECT, the app selected on its channel before
+    selecting: Option<Option<Aid>>,
+    /// Whether the buffers were in an unexpected state while handling the last request
+    unexpected_state: bool,
+    /// Whether the current command is the SELECT of the default app
+    implicit: bool,
+    /// Whether the session received its first command
+    started: bool,
 }
 
-impl<'pipe> ApduDispatch<'pipe> {
-    fn apdu_type(apdu: CommandView<'_>, interface: Interface) -> RequestType {
-        info!("instruction: {:?} {}", apdu.instruction(), apdu.p1);
-        if apdu.instruction() == Instruction::Select && (apdu.p1 & 0x04) != 0 {
-            Aid::try_new(apdu.data()).map_or_else(
-                |_err| {
-                    warn!("Failed to parse AID: {:?}", _err);
-                    RequestType::BadCommand(Status::IncorrectDataParameter)
-                },
-                |aid| RequestType::Select(aid, interface),
-            )
-        } else if apdu.instruction() == Instruction::GetResponse {
-            RequestType::GetResponse
-        } else {
-            RequestType::NewCommand(interface)
+impl<const C: usize, const R: usize> Session<C, R> {
+    pub const fn new() -> Self {
+        let mut channels = [LogicalChannel::Closed; LOGICAL_CHANNELS];
+        // The basic channel is always open
+        channels[0] = LogicalChannel::Open(None);
+        Self {
+            channels,
+            buffer: ApduBuffer::new(),
+            response_len_expected: 0,
+            extended: false,
+            was_request_chained: false,
+            id: 0,
+            pending: None,
+            selecting: None,
+            unexpected_state: false,
+            implicit: false,
+            started: false,
         }
     }
 
-    pub fn new(contact: Responder<'pipe>, contactless: Responder<'pipe>) -> Self {
-        ApduDispatch {
-            current_aid: None,
-            contact,
-            contactless,
-            interface: None,
-            was_request_chained: false,
-            response_len_expected: 0,
-            buffer: ApduBuffer {
-                raw: RawApduBuffer::None,
-            },
+    /// Return to the state of [`Session::new`] in place
+    fn clear(&mut self) {
+        self.channels = [LogicalChannel::Closed; LOGICAL_CHANNELS];
+        self.channels[0] = LogicalChannel::Open(None);
+        self.buffer.raw = RawApduBuffer::None;
+        self.buffer.command.clear();
+        self.buffer.reply.clear();
+        self.buffer.offset = 0;
+        self.response_len_expected = 0;
+        self.extended = false;
+        self.was_request_chained = false;
+        self.pending = None;
+        self.selecting = None;
+        self.unexpected_state = false;
+        self.implicit = false;
+        self.started = false;
+    }
+
+    /// Whether a command or response is being transmitted, or processed by an app
+    fn in_transaction(&self) -> bool {
+        self.pending.is_some() || !matches!(self.buffer.raw, RawApduBuffer::None)
+    }
+
+    /// AID of the app selected on an open logical channel, `Err` if the channel is closed
+    fn selected_aid(&self, channel: u8) -> Result<Option<Aid>> {
+        match self.channels.get(usize::from(channel)) {
+            Some(LogicalChannel::Open(aid)) => Ok(*aid),
+            _ => {
+                info!("logical channel {} is not open", channel);
+                Err(Status::LogicalChannelNotSupported)
+            }
         }
     }
 
-    // It would be nice to store `current_app` instead of constantly looking up by AID,
-    // but that won't work due to ownership rules
-    fn find_app<'a, 'b>(
-        aid: Option<&Aid>,
-        apps: &'a mut [&'b mut dyn App],
-    ) -> Option<&'a mut &'b mut dyn App> {
-        // match aid {
-        //     Some(aid) => apps.iter_mut().find(|app| aid.starts_with(app.rid())),
-        //     None => None,
-        // }
-        aid.and_then(move |aid| {
-            debug!("matching {:?}", aid);
-            apps.iter_mut().find(|app| {
-                // aid.starts_with(app.aid().truncated())
-                debug!("...against {:?}", app.aid());
-                app.aid().matches(aid)
-            })
-        })
+    /// Whether the app selected by `aid` is in use by this session: selected on any logical
+    /// channel, being selected, or deferring or streaming a response
+    fn in_use(&self, aid: &Aid, apps: &[&mut dyn App]) -> bool {
+        let index = app_index(aid, apps);
+        let responding = self.responding().and_then(|target| target.aid);
+        index.is_some()
+            && (self.channels.iter().any(|channel| match channel {
+                LogicalChannel::Open(Some(selected)) => app_index(selected, apps) == index,
+                _ => false,
+            }) || responding.is_some_and(|responding| app_index(&responding, apps) == index))
+    }
+
+    /// App or fallback handler that defers or streams the response to the current command
+    fn responding(&self) -> Option<Target> {
+        match self.buffer.raw {
+            RawApduBuffer::Response(Tail::Stream(target)) => Some(target),
+            _ => self.pending,
+        }
+    }
+
+    /// Report the unexpected state of the buffers found while handling the last request
+    fn take_error(&mut self, interface: Interface) -> core::result::Result<(), Error> {
+        match core::mem::take(&mut self.unexpected_state) {
+            true => Err(Error::UnexpectedState(interface)),
+            false => Ok(()),
+        }
+    }
+
+    /// The buffered command, and the cleared reply buffer for its response
+    fn command_and_reply(&mut self) -> Result<(CommandView<'_>, &mut response::Data<R>)> {
+        let command = match self.buffer.raw {
+            RawApduBuffer::Request => self.buffer.command.view(),
+            _ => {
+                info!("Unexpected buffer state.");
+                Err(Status::UnspecifiedCheckingError)
+            }
+        };
+        let command = command.inspect_err(|_| self.unexpected_state = true)?;
+        self.buffer.reply.clear();
+        Ok((command, &mut self.buffer.reply))
+    }
+
+    /// Context of the current command, received on `channel` of `interface` for the app selected
+    /// with `aid`
+    fn context(
+        &self,
+        interface: Interface,
+        channel: u8,
+        aid: Option<Aid>,
+        out: &(impl Sink + ?Sized),
+    ) -> Context {
+        let mut context = Context::new(interface);
+        context.channel = channel;
+        context.aid = aid;
+        context.chained = self.was_request_chained;
+        context.le = self.response_len_expected;
+        context.extended = self.extended;
+        context.max_frame_size = out.capacity();
+        context.session = self.id;
+        context.response_format = self.response_format();
+        context.implicit = self.implicit;
+        context
+    }
+
+    /// Response format requested by the current command, if it is a SELECT
+    fn response_format(&self) -> Option<ResponseFormat> {
+        if self.buffer.raw != RawApduBuffer::Request {
+            return None;
+        }
+        let apdu = self.buffer.command.view().ok()?;
+        (apdu.instruction() == Instruction::Select).then(|| ResponseFormat::from_p2(apdu.p2))
+    }
+
+    /// Drop the response data of a SELECT that asked for none, including the rest of a streamed
+    /// response
+    fn discard_response_data(&mut self, context: &Context, result: app::Result) -> app::Result {
+        if context.response_format != Some(ResponseFormat::NoData) {
+            return result;
+        }
+        info!("dropping the response data of the SELECT");
+        self.buffer.reply.clear();
+        match result {
+            Ok(Reply::More) => Ok(Reply::Ready),
+            result => result,
+        }
+    }
+
+    /// Process a command received on `interface` and write the response, including SW1SW2, to
+    /// `response`.
+    ///
+    /// Responses longer than `response` allows are sent in chunks retrieved with GET RESPONSE.
+    /// If the app defers its response, `response` is left empty and
+    /// [`processing`](Self::processing) returns `true`: the caller then calls
+    /// [`poll`](Self::poll) until the response is written, without blocking its loop.  The
+    /// commands that the selected app does not get are handled according to `routing`, which
+    /// should be the same for all the commands of the session.
+    pub fn process<const N: usize>(
+        &mut self,
+        apps: &mut [&mut dyn App],
+        routing: &mut Routing<'_>,
+        interface: Interface,
+        command: &[u8],
+        response: &mut heapless::Vec<u8, N>,
+    ) -> core::result::Result<(), Error> {
+        let response = response.as_mut_view();
+        response.clear();
+        if !self.started {
+            let mut out = Discard {
+                capacity: Sink::capacity(response),
+                events: &mut |_| {},
+            };
+            self.start(apps, interface, routing, &mut out, None);
+        }
+        let request = self.receive(command, interface);
+        self.handle(apps, interface, request, response, None, routing);
+        self.take_error(interface)
+    }
+
+    /// Poll the app or fallback handler that deferred the response to the last command given to
+    /// [`process`](Self::process), writing the response to `response` once it is ready.
+    pub fn poll<const N: usize>(
+        &mut self,
+        apps: &mut [&mut dyn App],
+        routing: &mut Routing<'_>,
+        interface: Interface,
+        response: &mut heapless::Vec<u8, N>,
+    ) -> core::result::Result<(), Error> {
+        self.poll_pending(apps, interface, response.as_mut_view(), routing);
+        self.take_error(interface)
+    }
+
+    /// Whether the response to the last command given to [`process`](Self::process) is
+    /// deferred.
+    ///
+    /// As long as this returns `true`, the caller should [`poll`](Self::poll) the session and ask
+    /// the reader for more time (CCID time extension, ISO 14443-4 S(WTX)).
+    pub fn processing(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// End the session after a reset of the card on `interface`.
+    ///
+    /// The apps selected on any logical channel are deselected with [`DeselectReason::Reset`],
+    /// the logical channels are closed, and buffered command chains and responses are dropped.
+    /// Then [`App::reset`] is called on all apps.
+    pub fn reset(&mut self, apps: &mut [&mut dyn App], interface: Interface) {
+        self.close(apps, DeselectReason::Reset, |_, _| {});
+        notify_end(apps, interface, DeselectReason::Reset);
+    }
+
+    /// End the session after `interface` lost power, for example when the reader is disconnected.
+    ///
+    /// Like [`reset`](Self::reset), with [`DeselectReason::PowerDown`] and [`App::power_down`].
+    pub fn power_down(&mut self, apps: &mut [&mut dyn App], interface: Interface) {
+        self.close(apps, DeselectReason::PowerDown, |_, _| {});
+        notify_end(apps, interface, DeselectReason::PowerDown);
+    }
+
+    /// Reset the session, calling `deselected` with the channel and AID of each deselected app
+    fn close(
+        &mut self,
+        apps: &mut [&mut dyn App],
+        reason: DeselectReason,
+        mut deselected: impl FnMut(u8, Aid),
+    ) {
+        for (channel, state) in self.channels.iter().enumerate() {
+            if let LogicalChannel::Open(Some(aid)) = state {
+                if let Some(app) = find_app(Some(aid), apps) {
+                    app.deselect(channel as u8, reason);
+                    deselected(channel as u8, *aid);
+                }
+            }
+        }
+        self.clear();
+        self.id = self.id.wrapping_add(1);
+    }
+
+    /// Start the session with its first command, received on `interface`, selecting the
+    /// default app of `routing` if any
+    fn start(
+        &mut self,
+        apps: &mut [&mut dyn App],
+        interface: Interface,
+        routing: &mut Routing<'_>,
+        out: &mut (impl Sink + ?Sized),
+        other: Option<&Self>,
+    ) {
+        self.started = true;
+        if let Some(aid) = routing.default_app(interface) {
+            self.select_implicitly(apps, interface, aid, out, other, routing);
+        }
     }
 
-    fn busy(&self) -> bool {
-        // the correctness of this relies on the properties of interchange - requester can only
-        // send request in the idle state.
-        use interchange::State::*;
-        let contact_busy = !matches!(self.contact.state(), Idle | Requested);
-        let contactless_busy = !matches!(self.contactless.state(), Idle | Requested);
-        contactless_busy || contact_busy
+    /// Select the default app `aid` on the basic channel, with a SELECT command asking for no
+    /// response data
+    fn select_implicitly(
+        &mut self,
+        apps: &mut [&mut dyn App],
+        interface: Interface,
+        aid: Aid,
+        out: &mut (impl Sink + ?Sized),
+        other: Option<&Self>,
+        routing: &mut Routing<'_>,
+    ) {
+        info!("selecting the default app");
+        let mut apdu = heapless::Vec::<u8, 21>::new();
+        apdu.extend_from_slice(&[0x00, 0xA4, 0x04, 0x0C, aid.len() as u8])
+            .ok();
+        apdu.extend_from_slice(aid.as_bytes()).ok();
+        let Ok(command) = CommandView::try_from(apdu.as_slice()) else {
+            return;
+        };
+        self.response_len_expected = 0;
+        self.extended = false;
+        self.was_request_chained = false;
+        self.buffer.request(command);
+
+        self.implicit = true;
+        self.handle_app_select(apps, aid, interface, out, other, routing);
+        if self.pending.is_some() {
+            info!("the default app deferred its selection");
+            self.cancel_pending(apps, interface, out, routing);
+        }
+        self.implicit = false;
+    }
+
+    /// Parse `message` and buffer it, returning [`RequestType::None`] if it is part of a chain
+    /// that needs to be acknowledged
+    fn receive(&mut self, message: &[u8], interface: Interface) -> Result<RequestType> {
+        let command = match parse_apdu(message) {
+            Ok(command) => command,
+            Err(status) => {
+                info!("Invalid apdu");
+                return Err(status);
+            }
+        };
+        self.response_len_expected = command.expected();
+        self.extended = command.extended;
+        // The Apdu may be standalone or part of a chain.
+        Ok(self.buffer_chained_apdu_if_needed(command, interface))
     }
 
     #[inline(never)]
@@ -156,7 +907,7 @@ impl<'pipe> ApduDispatch<'pipe> {
         // iso 7816-4 5.1.1
         // check Apdu level chaining and buffer if necessary.
         if !command.class().chain().not_the_last() {
-            let is_chaining = matches!(self.buffer.raw, RawApduBuffer::Request(_));
+            let is_chaining = self.buffer.raw == RawApduBuffer::Request;
 
             if is_chaining {
                 self.buffer.request(command);
@@ -165,35 +916,21 @@ impl<'pipe> ApduDispatch<'pipe> {
                 self.was_request_chained = true;
                 info!("combined chained commands.");
 
-                RequestType::NewCommand(interface)
+                RequestType::NewCommand(interface, logical_channel(command.class()))
             } else {
-                if self.buffer.raw == RawApduBuffer::None {
+                let apdu_type = apdu_type(command, interface);
+                if !matches!(apdu_type, RequestType::GetResponse(..)) {
                     self.was_request_chained = false;
                 }
-                let apdu_type = Self::apdu_type(command, interface);
                 match apdu_type {
                     // Keep buffer the same in case of GetResponse
-                    RequestType::GetResponse => (),
+                    RequestType::GetResponse(..) => (),
                     // Overwrite for everything else.
                     _ => self.buffer.request(command),
                 }
                 apdu_type
             }
         } else {
-            match interface {
-                // acknowledge
-                Interface::Contact => {
-                    self.contact
-                        .respond(Status::Success.into())
-                        .expect("Could not respond");
-                }
-                Interface::Contactless => {
-                    self.contactless
-                        .respond(Status::Success.into())
-                        .expect("Could not respond");
-                }
-            }
-
             if !command.data().is_empty() {
                 info!("chaining {} bytes", command.data().len());
                 self.buffer.request(command);
@@ -204,168 +941,271 @@ impl<'pipe> ApduDispatch<'pipe> {
         }
     }
 
-    fn parse_apdu<const S: usize>(message: &interchanges::Data) -> Result<iso7816::Command<S>> {
-        debug!(">> {}", hex_str!(message.as_slice(), sep:""));
-        match iso7816::Command::try_from(message) {
-            Ok(command) => Ok(command),
-            Err(_error) => {
-                info!("apdu bad");
-                match _error {
-                    FromSliceError::TooShort => {
-                        info!("TooShort");
-                    }
-                    FromSliceError::TooLong => {
-                        info!("TooLong");
-                    }
-                    FromSliceError::InvalidClass => {
-                        info!("InvalidClass");
-                    }
-                    FromSliceError::InvalidFirstBodyByteForExtended => {
-                        info!("InvalidFirstBodyByteForExtended");
-                    }
-                    FromSliceError::InvalidSliceLength => {
-                        info!("InvalidSliceLength");
-                    }
-                }
-                Err(Status::UnspecifiedCheckingError)
-            }
-        }
-    }
-
+    /// Handle a received request, sending the response to `out` unless the app defers it.
+    ///
+    /// `other` is the session of the other interface, if it is used concurrently.
     #[inline(never)]
-    fn check_for_request(&mut self) -> RequestType {
-        if !self.busy() {
-            // Check to see if we have gotten a message, giving priority to contactless.
-            let (message, interface) = if let Some(message) = self.contactless.take_request() {
-                (message, Interface::Contactless)
-            } else if let Some(message) = self.contact.take_request() {
-                (message, Interface::Contact)
-            } else {
-                return RequestType::None;
-            };
-
-            let apdu;
+    fn handle(
+        &mut self,
+        apps: &mut [&mut dyn App],
+        interface: Interface,
+        request: Result<RequestType>,
+        out: &mut (impl Sink + ?Sized),
+        other: Option<&Self>,
+        routing: &mut Routing<'_>,
+    ) {
+        // if there is a new request:
+        // - if it's a select, handle appropriately
+        // - else pass it on to currently selected app
+        match request {
+            // SELECT case
+            Ok(RequestType::Select(aid, interface, _)) => {
+                info!("Select");
+                self.handle_app_select(apps, aid, interface, out, other, routing);
+            }
 
-            if let Some(i) = self.interface {
-                if i != interface {
-                    apdu = Err(Status::UnspecifiedNonpersistentExecutionError)
+            Ok(RequestType::GetResponse(interface, channel)) => {
+                info!("GetResponse");
+                let status = match self.selected_aid(channel) {
+                    Ok(_) if self.buffer.channel == channel => None,
+                    Ok(_) => Some(Status::ConditionsOfUseNotSatisfied),
+                    Err(status) => Some(status),
+                };
+                if let Some(status) = status {
+                    // The buffered response is kept for the channel it belongs to
+                    out.event(Event::Rejected { interface, status });
+                    out.send(&[], status.to_u16());
+                } else if let RawApduBuffer::Response(_) = self.buffer.raw {
+                    out.event(Event::ResponseServed(interface));
+                    self.handle_stream_reply(apps, interface, out, routing);
                 } else {
-                    apdu = Self::parse_apdu::<{ interchanges::SIZE }>(&message);
+                    info!("Unexpected GetResponse request.");
+                    self.reject(interface, Status::UnspecifiedCheckingError, out);
                 }
-            } else {
-                self.interface = Some(interface);
-                apdu = Self::parse_apdu::<{ interchanges::SIZE }>(&message);
             }
 
-            // Parse the message as an APDU.
-            match apdu {
-                Ok(command) => {
-                    self.response_len_expected = command.expected();
-                    // The Apdu may be standalone or part of a chain.
-                    self.buffer_chained_apdu_if_needed(command.as_view(), interface)
-                }
-                Err(response) => {
-                    // If not a valid APDU, return error and don't pass to app.
-                    info!("Invalid apdu");
-                    match interface {
-                        Interface::Contactless => self
-                            .contactless
-                            .respond(response.into())
-                            .expect("cant respond"),
-                        Interface::Contact => {
-                            self.contact.respond(response.into()).expect("cant respond")
-                        }
-                    }
-                    RequestType::None
-                }
+            // command that is not a special command -- goes to app.
+            Ok(RequestType::NewCommand(interface, channel)) => {
+                info!("Command");
+                self.handle_app_command(apps, interface, channel, out, other, routing);
+            }
+            Ok(RequestType::ManageChannel(interface, channel)) => {
+                info!("Manage channel");
+                self.handle_manage_channel(apps, interface, channel, out);
+            }
+            Ok(RequestType::BadCommand(_, status)) => {
+                info!("Bad command");
+                self.reject(interface, status, out);
+            }
+            // acknowledge
+            Ok(RequestType::None) => {
+                out.event(Event::ChainBuffered(interface));
+                out.send(&[], Status::Success.to_u16());
+            }
+            // If not a valid APDU, return error and don't pass to app.
+            Err(status) => {
+                out.event(Event::Rejected { interface, status });
+                out.send(&[], status.to_u16());
             }
-        } else {
-            RequestType::None
         }
     }
 
+    fn reject(&mut self, interface: Interface, status: Status, out: &mut (impl Sink + ?Sized)) {
+        out.event(Event::Rejected { interface, status });
+        self.reply_error(status, out);
+    }
+
     #[inline(never)]
-    fn reply_error(&mut self, status: Status) {
-        self.respond(status.into());
+    fn reply_error(&mut self, status: Status, out: &mut (impl Sink + ?Sized)) {
+        out.send(&[], status.to_u16());
         self.buffer.raw = RawApduBuffer::None;
     }
 
+    /// Maximum length of the data field of a response chunk, so that it fits in `out` and in
+    /// the response buffer after concatenation of SW1SW2
+    fn max_response_len(&self, out: &(impl Sink + ?Sized)) -> usize {
+        self.response_len_expected
+            .min(out.capacity().min(R).saturating_sub(2))
+    }
+
     #[inline(never)]
-    fn handle_reply(&mut self) {
+    fn handle_reply(&mut self, out: &mut (impl Sink + ?Sized)) {
+        let max_response_len = self.max_response_len(out);
         // Consider if we need to reply via chaining method.
         // If the reader is using chaining, we will simply
         // reply 61XX, and put the response in a buffer.
         // It is up to the reader to then send GetResponse
         // requests, to which we will return up to `Le` bytes at a time.
-        let (new_state, response) = match &mut self.buffer.raw {
-            RawApduBuffer::Request(_) | RawApduBuffer::None => {
+        let buffer = &mut self.buffer;
+        let (new_state, len, return_code) = match buffer.raw {
+            RawApduBuffer::Request | RawApduBuffer::None => {
                 info!("Unexpected GetResponse request.");
-                (RawApduBuffer::None, Status::UnspecifiedCheckingError.into())
-            }
-            RawApduBuffer::Response(res) => {
-                let max_response_len = self.response_len_expected.min(MAX_INTERCHANGE_DATA);
-                if self.was_request_chained || res.len() > max_response_len {
-                    // Do not send more than the expected bytes
-                    let boundary = max_response_len.min(res.len());
-
-                    let to_send = &res[..boundary];
-                    let remaining = &res[boundary..];
-                    let mut message = interchanges::Data::from_slice(to_send).unwrap();
-                    let return_code = if remaining.len() > 255 {
-                        // XX = 00 indicates more than 255 bytes of data
-                        0x6100u16
-                    } else if !remaining.is_empty() {
-                        0x6100 + (remaining.len() as u16)
-                    } else {
-                        // Last chunk has success code
-                        0x9000
-                    };
-                    message
-                        .extend_from_slice(&return_code.to_be_bytes())
-                        .expect("Failed add to status bytes");
-                    if return_code == 0x9000 {
-                        (RawApduBuffer::None, message)
-                    } else {
-                        info!("Still {} bytes in response buffer", remaining.len());
-                        (
-                            RawApduBuffer::Response(response::Data::from_slice(remaining).unwrap()),
-                            message,
-                        )
+                (
+                    RawApduBuffer::None,
+                    0,
+                    Status::UnspecifiedCheckingError.to_u16(),
+                )
+            }
+            RawApduBuffer::Response(tail) => {
+                let res = buffer.remaining();
+                let chunked = self.was_request_chained || res.len() > max_response_len;
+                match tail {
+                    Tail::Status(status) if !chunked => {
+                        (RawApduBuffer::None, res.len(), status.to_u16())
                     }
-                } else {
-                    // Add success code
-                    res.extend_from_slice(&[0x90, 00])
-                        .expect("Failed to add the status bytes");
-                    (
-                        RawApduBuffer::None,
-                        interchanges::Data::from_slice(res.as_slice()).unwrap(),
-                    )
+                    _ => {
+                        // Do not send more than the expected bytes
+                        let boundary = max_response_len.min(res.len());
+
+                        let remaining = &res[boundary..];
+                        let return_code = match tail {
+                            // The length of the rest of a stream is unknown
+                            Tail::Stream(_) => 0x6100u16,
+                            // XX = 00 indicates more than 255 bytes of data
+                            _ if remaining.len() > 255 => 0x6100,
+                            _ if !remaining.is_empty() => 0x6100 + (remaining.len() as u16),
+                            // Last chunk has the final status
+                            Tail::Status(status) => status.to_u16(),
+                        };
+                        if remaining.is_empty() && matches!(tail, Tail::Status(_)) {
+                            (RawApduBuffer::None, boundary, return_code)
+                        } else {
+                            info!("Still {} bytes in response buffer", remaining.len());
+                            (RawApduBuffer::Response(tail), boundary, return_code)
+                        }
+                    }
+                }
+            }
+        };
+        out.send(&buffer.remaining()[..len], return_code);
+        buffer.offset += len;
+        buffer.raw = new_state;
+    }
+
+    #[inline(never)]
+    fn reply_data(&mut self, status: Status, out: &mut (impl Sink + ?Sized)) {
+        info!(
+            "buffered the response of {} bytes.",
+            self.buffer.reply.len()
+        );
+        self.buffer.response(Tail::Status(status));
+        self.handle_reply(out);
+    }
+
+    /// Read the streamed response of an app until the response buffer holds more than the next
+    /// chunk, or the stream ends
+    fn read_stream(
+        &mut self,
+        apps: &mut [&mut dyn App],
+        routing: &mut Routing<'_>,
+        context: &Context,
+        max_response_len: usize,
+    ) -> Result<()> {
+        let buffer = &mut self.buffer;
+        while let RawApduBuffer::Response(Tail::Stream(target)) = buffer.raw {
+            if buffer.remaining().len() > max_response_len {
+                break;
+            }
+            // Make room for the app, moving at most one chunk
+            let remaining = buffer.remaining().len();
+            buffer.reply.copy_within(buffer.offset.., 0);
+            buffer.reply.truncate(remaining);
+            buffer.offset = 0;
+
+            let mut handler = Handler::find(target, apps, routing).ok_or(Status::NotFound)?;
+            let tail = match handler.next_chunk(context, &mut buffer.reply)? {
+                Reply::More if buffer.reply.len() == remaining => {
+                    info!("no progress in streamed response");
+                    break;
+                }
+                Reply::More => continue,
+                Reply::Ready => Tail::Status(Status::Success),
+                Reply::Status(status) => {
+                    if !allows_data(status) {
+                        info!("dropping the rest of the response data");
+                        buffer.reply.clear();
+                    }
+                    Tail::Status(status)
                 }
+                Reply::Pending => return Err(Status::UnspecifiedNonpersistentExecutionError),
+            };
+            buffer.raw = RawApduBuffer::Response(tail);
+        }
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn handle_stream_reply(
+        &mut self,
+        apps: &mut [&mut dyn App],
+        interface: Interface,
+        out: &mut (impl Sink + ?Sized),
+        routing: &mut Routing<'_>,
+    ) {
+        let max_response_len = self.max_response_len(out);
+        let result = match self.buffer.raw {
+            RawApduBuffer::Response(Tail::Stream(target)) => {
+                let context = self.context(interface, target.channel, target.aid, out);
+                self.read_stream(apps, routing, &context, max_response_len)
             }
+            _ => Ok(()),
         };
-        self.buffer.raw = new_state;
-        self.respond(response);
+        match result {
+            Ok(()) => self.handle_reply(out),
+            Err(status) => {
+                info!("streamed response failed");
+                self.reply_error(status, out);
+            }
+        }
     }
 
     #[inline(never)]
-    fn handle_app_response(&mut self, response: &Result<()>, data: &response::Data) {
-        // put message into the response buffer
+    fn handle_app_response(
+        &mut self,
+        apps: &mut [&mut dyn App],
+        interface: Interface,
+        response: &app::Result,
+        target: Target,
+        out: &mut (impl Sink + ?Sized),
+        routing: &mut Routing<'_>,
+    ) {
         match response {
-            Ok(()) => {
-                info!("buffered the response of {} bytes.", data.len());
-                self.buffer.response(data);
-                self.handle_reply();
+            Ok(Reply::Ready) => self.reply_data(Status::Success, out),
+            Ok(Reply::Status(status)) => {
+                if !allows_data(*status) {
+                    info!("dropping the response data");
+                    self.buffer.reply.clear();
+                }
+                self.reply_data(*status, out)
+            }
+            Ok(Reply::More) => {
+                info!("app streams the response");
+                self.buffer.response(Tail::Stream(target));
+                self.handle_stream_reply(apps, interface, out, routing);
+            }
+            Ok(Reply::Pending) => {
+                // Keep the request unanswered, the app is polled until it replies.
+                info!("app deferred the response");
+                self.pending = Some(target);
             }
             Err(status) => {
                 // Just reply the error immediately.
                 info!("buffered app error");
-                self.reply_error(*status);
+                self.reply_error(*status, out);
             }
         }
     }
 
     #[inline(never)]
-    fn handle_app_select(&mut self, apps: &mut [&mut dyn App], aid: Aid, interface: Interface) {
+    fn handle_app_select(
+        &mut self,
+        apps: &mut [&mut dyn App],
+        aid: Aid,
+        interface: Interface,
+        out: &mut (impl Sink + ?Sized),
+        other: Option<&Self>,
+        routing: &mut Routing<'_>,
+    ) {
         // three cases:
         // - currently selected app has different AID -> deselect it, to give it
         //   the chance to clear sensitive state
@@ -378,97 +1218,1082 @@ impl<'pipe> ApduDispatch<'pipe> {
 
         // if there is a selected app with a different AID, deselect it
 
-        // select specified app in any case
-        if let Some(app) = Self::find_app(Some(&aid), apps) {
-            info!("Selected app");
-            let mut response = response::Data::new();
-            let result = match &self.buffer.raw {
-                RawApduBuffer::Request(apdu) => {
-                    app.select(interface, apdu.as_view(), &mut response)
-                }
-                _ => panic!("Unexpected buffer state."),
-            };
+        let (channel, occurrence) = match self.command_and_reply() {
+            Ok((apdu, _)) => (logical_channel(apdu.class()), Occurrence::from_p2(apdu.p2)),
+            Err(status) => return self.reject(interface, status, out),
+        };
+        let old_aid = match self.selected_aid(channel) {
+            Ok(old_aid) => old_aid,
+            Err(status) => return self.reject(interface, status, out),
+        };
+        let occurrence = match occurrence {
+            Ok(occurrence) => occurrence,
+            Err(status) => return self.reject(interface, status, out),
+        };
 
-            let old_aid = self.current_aid.replace(aid);
-            if let Some(old_aid) = old_aid {
-                if old_aid != aid {
-                    let app = Self::find_app(self.current_aid.as_ref(), apps).unwrap();
-                    // for now all apps will be happy with this.
-                    app.deselect();
+        // The channel keeps the AID the app was selected with, which can be an alias
+        let Some((index, aid)) = find_occurrence(&aid, occurrence, old_aid, apps) else {
+            info!("could not find app by aid: {}", hex_str!(&aid.as_bytes()));
+            return self.reject(interface, Status::NotFound, out);
+        };
+
+        if other.is_some_and(|other| other.in_use(&aid, apps)) {
+            info!("app is in use by the other interface");
+            return self.reject(
+                interface,
+                Status::UnspecifiedNonpersistentExecutionError,
+                out,
+            );
+        }
+
+        let context = self.context(interface, channel, Some(aid), out);
+        let (command, reply) = match self.command_and_reply() {
+            Ok(parts) => parts,
+            Err(status) => return self.reject(interface, status, out),
+        };
+        let result = apps[index].select(&context, command, reply);
+        let result = self.discard_response_data(&context, result);
+
+        // The selection only changes if the app accepts it
+        match result {
+            Ok(Reply::Pending) => self.selecting = Some(old_aid),
+            Ok(_) => self.commit_select(apps, interface, aid, channel, old_aid, out),
+            Err(_) => {
+                info!("app refused the selection");
+            }
+        }
+        let target = Target {
+            aid: Some(aid),
+            channel,
+        };
+        self.handle_app_response(apps, interface, &result, target, out, routing);
+    }
+
+    /// Make the app that accepted a SELECT the selected app of the channel, deselecting the app
+    /// selected before if it is another one
+    fn commit_select(
+        &mut self,
+        apps: &mut [&mut dyn App],
+        interface: Interface,
+        aid: Aid,
+        channel: u8,
+        old_aid: Option<Aid>,
+        out: &mut (impl Sink + ?Sized),
+    ) {
+        info!("Selected app");
+        self.channels[usize::from(channel)] = LogicalChannel::Open(Some(aid));
+        out.event(Event::Selected {
+            interface,
+            channel,
+            aid,
+        });
+        let index = app_index(&aid, apps);
+        if let Some(old_aid) = old_aid.filter(|old_aid| app_index(old_aid, apps) != index) {
+            if let Some(app) = find_app(Some(&old_aid), apps) {
+                // for now all apps will be happy with this.
+                app.deselect(channel, DeselectReason::AppSelected);
+                out.event(Event::Deselected {
+                    interface,
+                    channel,
+                    aid: old_aid,
+                    reason: DeselectReason::AppSelected,
+                });
+            }
+        }
+    }
+
+    #[inline(never)]
+    fn handle_app_command(
+        &mut self,
+        apps: &mut [&mut dyn App],
+        interface: Interface,
+        channel: u8,
+        out: &mut (impl Sink + ?Sized),
+        other: Option<&Self>,
+        routing: &mut Routing<'_>,
+    ) {
+        let aid = match self.selected_aid(channel) {
+            Ok(aid) => aid,
+            Err(status) => return self.reject(interface, status, out),
+        };
+
+        // Routed commands do not go to the selected app
+        match self.route(interface, routing.routes) {
+            Some(RouteTarget::App(aid)) => {
+                info!("routing the command to {}", hex_str!(&aid.as_bytes()));
+                if other.is_some_and(|other| other.in_use(&aid, apps)) {
+                    info!("app is in use by the other interface");
+                    return self.reject(
+                        interface,
+                        Status::UnspecifiedNonpersistentExecutionError,
+                        out,
+                    );
                 }
+                return self.call_app(apps, interface, aid, channel, out, routing);
             }
+            Some(RouteTarget::Fallback) => {
+                info!("routing the command to the fallback handler");
+                return self.handle_fallback(apps, interface, channel, out, other, routing);
+            }
+            None => {}
+        }
 
-            self.handle_app_response(&result, &response);
-        } else {
+        // if there is a selected app, send it the command
+        match aid.filter(|aid| find_app(Some(aid), apps).is_some()) {
+            Some(aid) => self.call_app(apps, interface, aid, channel, out, routing),
+            None => self.handle_fallback(apps, interface, channel, out, other, routing),
+        }
+    }
+
+    /// Target of the first of `routes` that matches the current command
+    fn route(&self, interface: Interface, routes: &[Route]) -> Option<RouteTarget> {
+        if self.buffer.raw != RawApduBuffer::Request {
+            return None;
+        }
+        let apdu = self.buffer.command.view().ok()?;
+        routes
+            .iter()
+            .find(|route| route.matches(interface, &apdu))
+            .map(Route::target)
+    }
+
+    /// Pass the current command, sent to `channel`, to the app `aid`
+    fn call_app(
+        &mut self,
+        apps: &mut [&mut dyn App],
+        interface: Interface,
+        aid: Aid,
+        channel: u8,
+        out: &mut (impl Sink + ?Sized),
+        routing: &mut Routing<'_>,
+    ) {
+        let Some(app) = find_app(Some(&aid), apps) else {
             info!("could not find app by aid: {}", hex_str!(&aid.as_bytes()));
-            self.reply_error(Status::NotFound);
+            return self.reject(interface, Status::NotFound, out);
+        };
+        let context = self.context(interface, channel, Some(aid), out);
+        let (command, reply) = match self.command_and_reply() {
+            Ok(parts) => parts,
+            Err(status) => return self.reject(interface, status, out),
+        };
+        let result = app.call(&context, command, reply);
+        out.event(Event::Dispatched {
+            interface,
+            channel,
+            aid,
+        });
+        let target = Target {
+            aid: Some(aid),
+            channel,
+        };
+        self.handle_app_response(apps, interface, &result, target, out, routing);
+    }
+
+    /// Pass a command sent to a channel without a selected app to the fallback handler, unless
+    /// it defers or streams a response to `other`
+    fn handle_fallback(
+        &mut self,
+        apps: &mut [&mut dyn App],
+        interface: Interface,
+        channel: u8,
+        out: &mut (impl Sink + ?Sized),
+        other: Option<&Self>,
+        routing: &mut Routing<'_>,
+    ) {
+        let Some(fallback) = routing.fallback.as_mut() else {
+            return self.reject(interface, routing.fallback_status, out);
+        };
+        let responding = other.and_then(Self::responding);
+        if responding.is_some_and(|target| target.aid.is_none()) {
+            info!("fallback handler is in use by the other interface");
+            return self.reject(
+                interface,
+                Status::UnspecifiedNonpersistentExecutionError,
+                out,
+            );
+        }
+        let context = self.context(interface, channel, None, out);
+        let (command, reply) = match self.command_and_reply() {
+            Ok(parts) => parts,
+            Err(status) => return self.reject(interface, status, out),
         };
+        let result = fallback.call(&context, command, reply);
+        out.event(Event::DispatchedToFallback { interface, channel });
+        let Some(result) = result else {
+            info!("command not handled by the fallback handler");
+            return self.reject(interface, routing.fallback_status, out);
+        };
+        let target = Target { aid: None, channel };
+        self.handle_app_response(apps, interface, &result, target, out, routing);
     }
 
+    /// MANAGE CHANNEL (ISO 7816-4 11.1.2)
+    ///
+    /// P1 = 00 opens the channel given in P2, or the first available one if P2 = 00, in which
+    /// case its number is returned. P1 = 80 closes the channel given in P2, or the one the command
+    /// was sent on if P2 = 00. The basic channel can not be closed.
     #[inline(never)]
-    fn handle_app_command(&mut self, apps: &mut [&mut dyn App], interface: Interface) {
-        // if there is a selected app, send it the command
-        let mut response = response::Data::new();
-        if let Some(app) = Self::find_app(self.current_aid.as_ref(), apps) {
-            let result = match &self.buffer.raw {
-                RawApduBuffer::Request(apdu) => app.call(interface, apdu.as_view(), &mut response),
-                _ => panic!("Unexpected buffer state."),
-            };
-            self.handle_app_response(&result, &response);
-        } else {
-            // TODO: correct error?
-            self.reply_error(Status::NotFound);
+    fn handle_manage_channel(
+        &mut self,
+        apps: &mut [&mut dyn App],
+        interface: Interface,
+        channel: u8,
+        out: &mut (impl Sink + ?Sized),
+    ) {
+        if let Err(status) = self.selected_aid(channel) {
+            return self.reject(interface, status, out);
+        }
+        let (p1, p2) = match self.command_and_reply() {
+            Ok((apdu, _)) => (apdu.p1, apdu.p2),
+            Err(status) => return self.reject(interface, status, out),
         };
+        match (p1, p2) {
+            (0x00, 0x00) => {
+                let Some(new_channel) = self
+                    .channels
+                    .iter()
+                    .position(|c| *c == LogicalChannel::Closed)
+                else {
+                    info!("no logical channel available");
+                    return self.reject(interface, Status::FunctionNotSupported, out);
+                };
+                info!("opened logical channel {}", new_channel);
+                self.channels[new_channel] = LogicalChannel::Open(None);
+                self.buffer.reply.clear();
+                self.buffer.reply.push(new_channel as u8).ok();
+                self.reply_data(Status::Success, out);
+            }
+            (0x00, new_channel) => match self.channels.get(usize::from(new_channel)) {
+                Some(LogicalChannel::Closed) => {
+                    info!("opened logical channel {}", new_channel);
+                    self.channels[usize::from(new_channel)] = LogicalChannel::Open(None);
+                    self.reply_data(Status::Success, out);
+                }
+                Some(LogicalChannel::Open(_)) => {
+                    self.reject(interface, Status::IncorrectP1OrP2Parameter, out)
+                }
+                None => self.reject(interface, Status::LogicalChannelNotSupported, out),
+            },
+            (0x80, to_close) => {
+                let to_close = if to_close == 0 { channel } else { to_close };
+                if to_close == 0 {
+                    return self.reject(interface, Status::IncorrectP1OrP2Parameter, out);
+                }
+                let aid = match self.selected_aid(to_close) {
+                    Ok(aid) => aid,
+                    Err(status) => return self.reject(interface, status, out),
+                };
+                if let (Some(aid), Some(app)) = (aid, find_app(aid.as_ref(), apps)) {
+                    app.deselect(to_close, DeselectReason::ChannelClosed);
+                    out.event(Event::Deselected {
+                        interface,
+                        channel: to_close,
+                        aid,
+                        reason: DeselectReason::ChannelClosed,
+                    });
+                }
+                info!("closed logical channel {}", to_close);
+                self.channels[usize::from(to_close)] = LogicalChannel::Closed;
+                self.reply_data(Status::Success, out);
+            }
+            _ => self.reject(interface, Status::IncorrectP1OrP2Parameter, out),
+        }
     }
 
-    pub fn poll(&mut self, apps: &mut [&mut dyn App]) -> Option<Interface> {
-        // Only take on one transaction at a time.
-        let request_type = self.check_for_request();
+    /// Poll the app or fallback handler that deferred the response to the current command, if
+    /// any
+    #[inline(never)]
+    fn poll_pending(
+        &mut self,
+        apps: &mut [&mut dyn App],
+        interface: Interface,
+        out: &mut (impl Sink + ?Sized),
+        routing: &mut Routing<'_>,
+    ) {
+        let Some(pending) = self.pending else {
+            return;
+        };
+        let Some(mut handler) = Handler::find(pending, apps, routing) else {
+            self.pending = None;
+            self.selecting = None;
+            return self.reject(interface, Status::NotFound, out);
+        };
+        let context = self.context(interface, pending.channel, pending.aid, out);
+        self.buffer.reply.clear();
+        let mut result = handler.poll(&context, &mut self.buffer.reply);
+        if result != Ok(Reply::Pending) {
+            self.pending = None;
+            if let (Some(old_aid), Some(aid)) = (self.selecting.take(), pending.aid) {
+                result = self.discard_response_data(&context, result);
+                if result.is_ok() {
+                    self.commit_select(apps, interface, aid, pending.channel, old_aid, out);
+                } else {
+                    info!("app refused the selection");
+                }
+            }
+            self.handle_app_response(apps, interface, &result, pending, out, routing);
+        }
+    }
 
-        // if there is a new request:
-        // - if it's a select, handle appropriately
-        // - else pass it on to currently selected app
-        // if there is no new request, poll currently selected app
-        match request_type {
-            // SELECT case
-            RequestType::Select(aid, interface) => {
-                info!("Select");
-                self.handle_app_select(apps, aid, interface);
+    /// Drop the current command if its response is deferred, telling the app or fallback handler
+    /// that deferred it
+    fn cancel_pending(
+        &mut self,
+        apps: &mut [&mut dyn App],
+        interface: Interface,
+        out: &mut (impl Sink + ?Sized),
+        routing: &mut Routing<'_>,
+    ) {
+        let Some(pending) = self.pending.take() else {
+            return;
+        };
+        let context = self.context(interface, pending.channel, pending.aid, out);
+        self.selecting = None;
+        self.buffer.raw = RawApduBuffer::None;
+        if let Some(mut handler) = Handler::find(pending, apps, routing) {
+            handler.cancel(&context);
+        }
+    }
+}
+
+impl<const C: usize, const R: usize> Default for Session<C, R> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn interface_index(interface: Interface) -> usize {
+    match interface {
+        Interface::Contact => 0,
+        Interface::Contactless => 1,
+    }
+}
+
+/// Tell all apps that the session on `interface` ended for `reason`
+fn notify_end(apps: &mut [&mut dyn App], interface: Interface, reason: DeselectReason) {
+    for app in apps.iter_mut() {
+        match reason {
+            DeselectReason::PowerDown => app.power_down(interface),
+            _ => app.reset(interface),
+        }
+    }
+}
+
+/// AID of `app` that matches `aid`: its own AID or one of its aliases
+fn matching_aid(app: &dyn App, aid: &[u8]) -> Option<Aid> {
+    let own = app.aid();
+    if own.matches(aid) {
+        return Some(own);
+    }
+    app.aliases()
+        .iter()
+        .find(|alias| alias.matches(aid))
+        .copied()
+}
+
+/// Index of the app selected by `aid`, preferring an app registered with exactly this AID over
+/// one that only matches it
+fn app_index(aid: &Aid, apps: &[&mut dyn App]) -> Option<usize> {
+    apps.iter()
+        .position(|app| app.aid() == *aid || app.aliases().contains(aid))
+        .or_else(|| {
+            apps.iter()
+                .position(|app| matching_aid(&**app, aid).is_some())
+        })
+}
+
+/// App selected by a SELECT with the partial or full `aid`, and the AID it is selected with.
+///
+/// The AIDs of the apps are matched in a stable order: the apps in the order they are given,
+/// each with its own AID first and then its aliases.  The next occurrence is the one after
+/// `current`, if it matches `aid`.
+fn find_occurrence(
+    aid: &[u8],
+    occurrence: Occurrence,
+    current: Option<Aid>,
+    apps: &[&mut dyn App],
+) -> Option<(usize, Aid)> {
+    let mut matching = apps
+        .iter()
+        .enumerate()
+        .flat_map(|(index, app)| {
+            core::iter::once(app.aid())
+                .chain(app.aliases().iter().copied())
+                .map(move |registered| (index, registered))
+        })
+        .filter(|(_, registered)| registered.matches(aid));
+    match (occurrence, current) {
+        (Occurrence::Next, Some(current)) if current.matches(aid) => {
+            matching.find(|(_, registered)| *registered == current)?;
+            matching.next()
+        }
+        _ => matching.next(),
+    }
+}
+
+/// Whether ISO 7816-4 allows response data with `status`: success and the warnings `62XX` and
+/// `63XX`
+fn allows_data(status: Status) -> bool {
+    let sw = status.to_u16();
+    sw == Status::Success.to_u16() || matches!(sw >> 8, 0x62 | 0x63)
+}
+
+// It would be nice to store `current_app` instead of constantly looking up by AID,
+// but that won't work due to ownership rules
+fn find_app<'a, 'b>(
+    aid: Option<&Aid>,
+    apps: &'a mut [&'b mut dyn App],
+) -> Option<&'a mut &'b mut dyn App> {
+    // match aid {
+    //     Some(aid) => apps.iter_mut().find(|app| aid.starts_with(app.rid())),
+    //     None => None,
+    // }
+    aid.and_then(move |aid| {
+        debug!("matching {:?}", aid);
+        let index = app_index(aid, apps)?;
+        apps.get_mut(index)
+    })
+}
+
+fn apdu_type(apdu: CommandView<'_>, interface: Interface) -> RequestType {
+    info!("instruction: {:?} {}", apdu.instruction(), apdu.p1);
+    let channel = logical_channel(apdu.class());
+    let interindustry = matches!(
+        apdu.class().range(),
+        Range::Interindustry(Interindustry::First | Interindustry::Further)
+    );
+    if apdu.instruction() == Instruction::Select && (apdu.p1 & 0x04) != 0 {
+        Aid::try_new(apdu.data()).map_or_else(
+            |_err| {
+                warn!("Failed to parse AID: {:?}", _err);
+                RequestType::BadCommand(interface, Status::IncorrectDataParameter)
+            },
+            |aid| RequestType::Select(aid, interface, channel),
+        )
+    } else if apdu.instruction() == Instruction::GetResponse {
+        RequestType::GetResponse(interface, channel)
+    } else if interindustry && u8::from(apdu.instruction()) == MANAGE_CHANNEL {
+        RequestType::ManageChannel(interface, channel)
+    } else {
+        RequestType::NewCommand(interface, channel)
+    }
+}
+
+fn parse_apdu(message: &[u8]) -> Result<CommandView<'_>> {
+    debug!(">> {}", hex_str!(message, sep:""));
+    match CommandView::try_from(message) {
+        Ok(command) => Ok(command),
+        Err(_error) => {
+            info!("apdu bad");
+            match _error {
+                FromSliceError::TooShort => {
+                    info!("TooShort");
+                }
+                FromSliceError::TooLong => {
+                    info!("TooLong");
+                }
+                FromSliceError::InvalidClass => {
+                    info!("InvalidClass");
+                }
+                FromSliceError::InvalidFirstBodyByteForExtended => {
+                    info!("InvalidFirstBodyByteForExtended");
+                }
+                FromSliceError::InvalidSliceLength => {
+                    info!("InvalidSliceLength");
+                }
             }
+            Err(Status::UnspecifiedCheckingError)
+        }
+    }
+}
 
-            RequestType::GetResponse => {
-                info!("GetResponse");
-                self.handle_reply();
+/// Destination of the responses of a [`Session`]
+trait Sink {
+    /// Maximum length of a response, including SW1SW2
+    fn capacity(&self) -> usize;
+
+    fn send(&mut self, data: &[u8], status: u16);
+
+    fn event(&mut self, _event: Event) {}
+}
+
+/// Transport used to send the responses of a session, recording whether sending failed
+struct Output<'a, T: ?Sized> {
+    transport: &'a mut T,
+    failed: bool,
+    events: &'a mut dyn FnMut(Event),
+}
+
+impl<T: Transport + ?Sized> Sink for Output<'_, T> {
+    fn capacity(&self) -> usize {
+        self.transport.capacity()
+    }
+
+    fn send(&mut self, data: &[u8], status: u16) {
+        if self.transport.send(data, status).is_err() {
+            info!("could not send the response");
+            self.failed = true;
+            // A canceled request still needs to be released
+            if self.transport.is_canceled() {
+                self.transport.acknowledge_cancel();
             }
+        }
+    }
 
-            // command that is not a special command -- goes to app.
-            RequestType::NewCommand(interface) => {
-                info!("Command");
-                self.handle_app_command(apps, interface);
+    fn event(&mut self, event: Event) {
+        (self.events)(event);
+    }
+}
+
+/// Destination of the responses to the commands of the dispatch itself, which are dropped
+struct Discard<'a> {
+    capacity: usize,
+    events: &'a mut dyn FnMut(Event),
+}
+
+impl Sink for Discard<'_> {
+    fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    fn send(&mut self, _data: &[u8], _status: u16) {}
+
+    fn event(&mut self, event: Event) {
+        (self.events)(event);
+    }
+}
+
+impl Sink for heapless::vec::VecView<u8> {
+    fn capacity(&self) -> usize {
+        heapless::vec::VecView::capacity(self)
+    }
+
+    fn send(&mut self, data: &[u8], status: u16) {
+        debug!("<< {} {:04X}", hex_str!(data, sep:""), status);
+        self.clear();
+        self.extend_from_slice(data).ok();
+        self.extend_from_slice(&status.to_be_bytes()).ok();
+    }
+}
+
+/// Dispatch of the commands received on `N` [`Port`]s to the apps.
+///
+/// The session of the ports, or of the contact ports if contactless ports have their own, is
+/// stored in `S`: owned with [`ApduDispatch::new`], or borrowed with [`ApduDispatch::new_in`].
+/// A session is bound to the first port that uses it until it is [reset](ApduDispatch::reset).
+///
+/// `C` and `R` are the sizes of the buffers of the [`Session`]s.  Other sizes than the default
+/// ones can be used with sessions provided to [`new_in`](ApduDispatch::new_in),
+/// [`new_concurrent_in`](ApduDispatch::new_concurrent_in) or
+/// [`with_ports`](ApduDispatch::with_ports).
+pub struct ApduDispatch<
+    'pipe,
+    const C: usize = COMMAND_BUFFER_SIZE,
+    const R: usize = { response::SIZE },
+    T = Responder<'pipe>,
+    const N: usize = 2,
+    S = Session<C, R>,
+> {
+    ports: [Port<T>; N],
+    /// Port bound to `session` and to `contactless_session`
+    bound: [Option<usize>; 2],
+    conflict_policy: ConflictPolicy,
+    scheduling_policy: SchedulingPolicy,
+    /// Port of the last request taken
+    last_served: Option<usize>,
+    routing: Routing<'pipe>,
+
+    session: S,
+    contactless_session: Option<&'pipe mut Session<C, R>>,
+}
+
+impl<'pipe> ApduDispatch<'pipe> {
+    pub fn new(contact: Responder<'pipe>, contactless: Responder<'pipe>) -> Self {
+        Self::with_ports(interface_ports(contact, contactless), Session::new(), None)
+    }
+
+    /// Create a dispatch that keeps separate sessions for both interfaces, so that they can be
+    /// used at the same time.
+    ///
+    /// The contactless interface uses `contactless_session`, which can be stored in a `static`.
+    /// An app can only be used by one interface at a time: selecting it on the other interface
+    /// fails while it is selected, being selected, or deferring or streaming a response, until it
+    /// is deselected or the session is [reset](Self::reset).
+    pub fn new_concurrent(
+        contact: Responder<'pipe>,
+        contactless: Responder<'pipe>,
+        contactless_session: &'pipe mut Session,
+    ) -> Self {
+        Self::with_ports(
+            interface_ports(contact, contactless),
+            Session::new(),
+            Some(contactless_session),
+        )
+    }
+}
+
+impl<'pipe, const C: usize, const R: usize, const I: usize>
+    ApduDispatch<'pipe, C, R, Responder<'pipe, I>, 2, &'pipe mut Session<C, R>>
+{
+    /// Like [`new`](ApduDispatch::new), but with the command and response buffers in `session`,
+    /// which can be stored in a `static`.
+    pub fn new_in(
+        contact: Responder<'pipe, I>,
+        contactless: Responder<'pipe, I>,
+        session: &'pipe mut Session<C, R>,
+    ) -> Self {
+        Self::with_ports(interface_ports(contact, contactless), session, None)
+    }
+
+    /// Like [`new_concurrent`](ApduDispatch::new_concurrent), but with the command and response
+    /// buffers of the contact interface in `contact_session`.
+    pub fn new_concurrent_in(
+        contact: Responder<'pipe, I>,
+        contactless: Responder<'pipe, I>,
+        contact_session: &'pipe mut Session<C, R>,
+        contactless_session: &'pipe mut Session<C, R>,
+    ) -> Self {
+        Self::with_ports(
+            interface_ports(contact, contactless),
+            contact_session,
+            Some(contactless_session),
+        )
+    }
+}
+
+/// Ports of the contact and contactless interchanges, with priority to contactless
+fn interface_ports<'pipe, const I: usize>(
+    contact: Responder<'pipe, I>,
+    contactless: Responder<'pipe, I>,
+) -> [Port<Responder<'pipe, I>>; 2] {
+    [
+        Port::new(contact, Interface::Contact),
+        Port::new(contactless, Interface::Contactless).with_priority(1),
+    ]
+}
+
+impl<
+        'pipe,
+        const C: usize,
+        const R: usize,
+        T: Transport,
+        const N: usize,
+        S: BorrowMut<Session<C, R>>,
+    > ApduDispatch<'pipe, C, R, T, N, S>
+{
+    /// Create a dispatch for any number of ports.
+    ///
+    /// All ports use `session`, unless `contactless_session` is given: then the ports tagged as
+    /// contactless use it, and can be used at the same time as the contact ones, as with
+    /// [`new_concurrent`](ApduDispatch::new_concurrent).  Transports of different types can be
+    /// used together as `&mut dyn Transport`.
+    pub fn with_ports(
+        ports: [Port<T>; N],
+        mut session: S,
+        mut contactless_session: Option<&'pipe mut Session<C, R>>,
+    ) -> Self {
+        session.borrow_mut().clear();
+        if let Some(session) = contactless_session.as_deref_mut() {
+            session.clear();
+        }
+        ApduDispatch {
+            ports,
+            bound: [None; 2],
+            conflict_policy: ConflictPolicy::Reject,
+            scheduling_policy: SchedulingPolicy::Priority,
+            last_served: None,
+            routing: Routing::new(),
+            session,
+            contactless_session,
+        }
+    }
+
+    /// Set how requests from one port are handled while its session is bound to another one.
+    pub fn with_conflict_policy(mut self, policy: ConflictPolicy) -> Self {
+        self.conflict_policy = policy;
+        self
+    }
+
+    /// Set the order in which the ports with a command are served.
+    pub fn with_scheduling_policy(mut self, policy: SchedulingPolicy) -> Self {
+        self.scheduling_policy = policy;
+        self
+    }
+
+    /// Set the default apps, the routes and the fallback handler.
+    pub fn with_routing(mut self, routing: Routing<'pipe>) -> Self {
+        self.routing = routing;
+        self
+    }
+
+    /// Set the priority of the ports of `interface`.
+    pub fn with_interface_priority(mut self, interface: Interface, priority: u8) -> Self {
+        for port in &mut self.ports {
+            if port.interface == interface {
+                port.priority = priority;
             }
-            RequestType::BadCommand(status) => {
-                info!("Bad command");
-                self.reply_error(status);
+        }
+        self
+    }
+
+    /// Ports in the order they are served: by decreasing priority, then in the given order,
+    /// rearranged according to the scheduling policy
+    fn port_order(&self) -> [usize; N] {
+        let mut order: [usize; N] = core::array::from_fn(|port| port);
+        order.sort_unstable_by_key(|&port| (core::cmp::Reverse(self.ports[port].priority), port));
+        let last = self
+            .last_served
+            .and_then(|last| order.iter().position(|&port| port == last));
+        match (self.scheduling_policy, last) {
+            (SchedulingPolicy::RoundRobin, Some(last)) => order.rotate_left(last + 1),
+            (SchedulingPolicy::LastServedFirst, Some(last)) => order[..=last].rotate_right(1),
+            _ => {}
+        }
+        order
+    }
+
+    /// Index in `bound` of the session used by `port`
+    fn session_index(&self, port: usize) -> usize {
+        match (self.ports[port].interface, &self.contactless_session) {
+            (Interface::Contactless, Some(_)) => 1,
+            _ => 0,
+        }
+    }
+
+    fn session_ref(&self, port: usize) -> &Session<C, R> {
+        match (self.session_index(port), &self.contactless_session) {
+            (1, Some(session)) => session,
+            _ => self.session.borrow(),
+        }
+    }
+
+    /// Transport and session of `port`, and the session of the other interface if contactless
+    /// ports have their own
+    fn parts(&mut self, port: usize) -> (&mut T, &mut Session<C, R>, Option<&Session<C, R>>) {
+        let index = self.session_index(port);
+        let transport = &mut self.ports[port].transport;
+        match (index, &mut self.contactless_session) {
+            (1, Some(session)) => (transport, session, Some(self.session.borrow())),
+            (_, Some(session)) => (transport, self.session.borrow_mut(), Some(session)),
+            (_, None) => (transport, self.session.borrow_mut(), None),
+        }
+    }
+
+    /// Let `f` handle a request with the session of `port`, sending the responses to its
+    /// transport
+    fn run(
+        &mut self,
+        port: usize,
+        events: &mut dyn FnMut(Event),
+        f: impl FnOnce(&mut Session<C, R>, &mut Output<'_, T>, Option<&Session<C, R>>),
+    ) -> core::result::Result<(), Error> {
+        let interface = self.ports[port].interface;
+        let (transport, session, other) = self.parts(port);
+        let mut out = Output {
+            transport,
+            failed: false,
+            events,
+        };
+        f(session, &mut out, other);
+        if out.failed {
+            session.take_error(interface).ok();
+            return Err(Error::Transport(interface));
+        }
+        session.take_error(interface)
+    }
+
+    /// Whether the session used by `port` is currently in use by this port
+    fn owns_session(&self, port: usize) -> bool {
+        self.bound[self.session_index(port)] == Some(port)
+    }
+
+    /// Whether a port that shares the session of `port` is in the middle of an exchange
+    fn busy(&self, port: usize) -> bool {
+        let index = self.session_index(port);
+        (0..N).any(|other| {
+            self.session_index(other) == index && self.ports[other].transport.is_busy()
+        })
+    }
+
+    /// Whether a request from `port` has to wait for the transaction on the port bound to its
+    /// session
+    fn must_wait(&self, port: usize) -> bool {
+        self.conflict_policy == ConflictPolicy::Wait
+            && self.bound[self.session_index(port)].is_some_and(|bound| bound != port)
+            && self.session_ref(port).in_transaction()
+    }
+
+    #[inline(never)]
+    fn check_for_request(
+        &mut self,
+        apps: &mut [&mut dyn App],
+        routing: &mut Routing<'_>,
+        events: &mut dyn FnMut(Event),
+    ) -> Option<(usize, Result<RequestType>)> {
+        // Check to see if we have gotten a message, in the order of the scheduling policy.
+        let mut request = None;
+        for port in self.port_order() {
+            if !self.busy(port) && !self.must_wait(port) {
+                let index = self.session_index(port);
+                if self.conflict_policy == ConflictPolicy::Wait
+                    && self.ports[port].transport.has_request()
+                {
+                    if let Some(bound) = self.bound[index].filter(|bound| *bound != port) {
+                        info!("releasing port {} for port {}", bound, port);
+                        self.reset_port(apps, bound, DeselectReason::InterfaceLost, events);
+                    }
+                }
+                if self.ports[port].transport.has_request() {
+                    request = Some(port);
+                    break;
+                }
             }
-            RequestType::None => {}
         }
+        let port = request?;
+        self.last_served = Some(port);
 
-        // slight priority to contactless.
-        if self.contactless.state() == interchange::State::Responded {
-            Some(Interface::Contactless)
-        } else if self.contact.state() == interchange::State::Responded {
-            Some(Interface::Contact)
-        } else {
-            None
+        let index = self.session_index(port);
+        let accepted = match self.bound[index] {
+            Some(bound) => bound == port,
+            None => {
+                self.bound[index] = Some(port);
+                events(Event::Bound(self.ports[port].interface));
+                true
+            }
+        };
+        if accepted && !self.session_ref(port).started {
+            self.start_session(apps, port, routing, events);
         }
+
+        // The request is read in place, and copied to the session before the response is
+        // written over it.
+        let interface = self.ports[port].interface;
+        let (transport, session, _) = self.parts(port);
+        let message = transport.take_request()?;
+        let request = if accepted {
+            session.receive(message, interface)
+        } else {
+            Err(Status::UnspecifiedNonpersistentExecutionError)
+        };
+        Some((port, request))
     }
 
+    /// Start the session of `port` with its first command
+    fn start_session(
+        &mut self,
+        apps: &mut [&mut dyn App],
+        port: usize,
+        routing: &mut Routing<'_>,
+        events: &mut dyn FnMut(Event),
+    ) {
+        let interface = self.ports[port].interface;
+        let (transport, session, other) = self.parts(port);
+        let mut out = Discard {
+            capacity: transport.capacity(),
+            events,
+        };
+        session.start(apps, interface, routing, &mut out, other);
+    }
+
+    /// Poll the app or fallback handler that deferred the response to the current command on
+    /// `port`, if any
     #[inline(never)]
-    fn respond(&mut self, message: interchanges::Data) {
-        debug!("<< {}", hex_str!(message.as_slice(), sep:""));
-        match self.interface.unwrap() {
-            Interface::Contactless => self.contactless.respond(message).expect("cant respond"),
-            Interface::Contact => self.contact.respond(message).expect("cant respond"),
+    fn poll_pending(
+        &mut self,
+        apps: &mut [&mut dyn App],
+        port: usize,
+        routing: &mut Routing<'_>,
+        events: &mut dyn FnMut(Event),
+    ) -> core::result::Result<(), Error> {
+        if !self.owns_session(port) {
+            return Ok(());
+        }
+        let interface = self.ports[port].interface;
+        let (transport, session, _) = self.parts(port);
+        if session.pending.is_none() {
+            return Ok(());
+        }
+
+        if transport.is_canceled() {
+            info!("deferred request canceled");
+            transport.acknowledge_cancel();
+            let mut out = Discard {
+                capacity: transport.capacity(),
+                events,
+            };
+            session.cancel_pending(apps, interface, &mut out, routing);
+            return Ok(());
+        }
+
+        self.run(port, events, |session, out, _| {
+            session.poll_pending(apps, interface, out, routing)
+        })
+    }
+
+    /// Whether an app is still processing a request received on a port of `interface`.
+    ///
+    /// This is the case while the app that received the request defers its response.  As long as
+    /// this returns `true`, the transport layer of the interface should ask the reader for more
+    /// time (CCID time extension, ISO 14443-4 S(WTX)) instead of letting it time out.
+    pub fn processing(&self, interface: Interface) -> bool {
+        (0..N).any(|port| {
+            self.ports[port].interface == interface
+                && self.owns_session(port)
+                && self.session_ref(port).processing()
+        })
+    }
+
+    /// End the session on the ports of the given interface after a reset of the card, for
+    /// example a CCID warm reset.
+    ///
+    /// If a port of the interface is in use, its session is [reset](Session::reset), and the
+    /// session is released so that the other ports can use it.  Then [`App::reset`] is called on
+    /// all apps.
+    pub fn reset(&mut self, apps: &mut [&mut dyn App], interface: Interface) {
+        self.reset_events(apps, interface, &mut |_| {});
+    }
+
+    /// Like [`reset`](Self::reset), calling `events` with the [`Event`]s of the deselected apps
+    /// and of the released session.
+    pub fn reset_events(
+        &mut self,
+        apps: &mut [&mut dyn App],
+        interface: Interface,
+        events: &mut dyn FnMut(Event),
+    ) {
+        self.end_session(apps, interface, DeselectReason::Reset, events);
+    }
+
+    /// End the session on the ports of the given interface after it lost power, for example when
+    /// the NFC field is lost or the CCID slot is powered off.
+    ///
+    /// Like [`reset`](Self::reset), with [`DeselectReason::PowerDown`] and [`App::power_down`].
+    pub fn power_down(&mut self, apps: &mut [&mut dyn App], interface: Interface) {
+        self.power_down_events(apps, interface, &mut |_| {});
+    }
+
+    /// Like [`power_down`](Self::power_down), calling `events` with the [`Event`]s of the
+    /// deselected apps and of the released session.
+    pub fn power_down_events(
+        &mut self,
+        apps: &mut [&mut dyn App],
+        interface: Interface,
+        events: &mut dyn FnMut(Event),
+    ) {
+        self.end_session(apps, interface, DeselectReason::PowerDown, events);
+    }
+
+    fn end_session(
+        &mut self,
+        apps: &mut [&mut dyn App],
+        interface: Interface,
+        reason: DeselectReason,
+        events: &mut dyn FnMut(Event),
+    ) {
+        for port in 0..N {
+            if self.ports[port].interface == interface && self.owns_session(port) {
+                self.reset_port(apps, port, reason, events);
+            }
+        }
+        notify_end(apps, interface, reason);
+    }
+
+    fn reset_port(
+        &mut self,
+        apps: &mut [&mut dyn App],
+        port: usize,
+        reason: DeselectReason,
+        events: &mut dyn FnMut(Event),
+    ) {
+        info!("resetting session on port {}", port);
+
+        let interface = self.ports[port].interface;
+        let (transport, session, _) = self.parts(port);
+        let pending = session.pending.is_some();
+        session.close(apps, reason, |channel, aid| {
+            events(Event::Deselected {
+                interface,
+                channel,
+                aid,
+                reason,
+            })
+        });
+
+        // A deferred request still needs to be answered to release the transport
+        if transport.is_canceled() {
+            transport.acknowledge_cancel();
+        } else if pending {
+            let status = Status::UnspecifiedNonpersistentExecutionError;
+            transport.send(&[], status.to_u16()).ok();
         }
+
+        let index = self.session_index(port);
+        self.bound[index] = None;
+        events(Event::Released(interface));
+    }
+
+    /// Handle the next command and poll the apps that deferred their responses.
+    ///
+    /// Returns the interface of the first port, in the order of the [`SchedulingPolicy`], that
+    /// has a response to send.  If handling a request failed, the first error is returned, and
+    /// the dispatch can still be polled.
+    pub fn poll(
+        &mut self,
+        apps: &mut [&mut dyn App],
+    ) -> core::result::Result<Option<Interface>, Error> {
+        self.poll_events(apps, &mut |_| {})
+    }
+
+    /// Like [`poll`](Self::poll), calling `events` with each [`Event`] that happens while
+    /// handling the commands.
+    pub fn poll_events(
+        &mut self,
+        apps: &mut [&mut dyn App],
+        events: &mut dyn FnMut(Event),
+    ) -> core::result::Result<Option<Interface>, Error> {
+        // The routing is borrowed by the sessions while the ports are
+        let mut routing = core::mem::take(&mut self.routing);
+        let result = self.poll_routed(apps, &mut routing, events);
+        self.routing = routing;
+        result
+    }
+
+    fn poll_routed(
+        &mut self,
+        apps: &mut [&mut dyn App],
+        routing: &mut Routing<'_>,
+        events: &mut dyn FnMut(Event),
+    ) -> core::result::Result<Option<Interface>, Error> {
+        // Only take on one transaction at a time.
+        let request = self.check_for_request(apps, routing, events);
+
+        // if there is no new request, poll currently selected app
+        let mut result = Ok(());
+        let mut handled = None;
+        if let Some((port, request)) = request {
+            if matches!(
+                request,
+                Ok(RequestType::Select(..) | RequestType::NewCommand(..))
+            ) {
+                handled = Some(port);
+            }
+            let interface = self.ports[port].interface;
+            result = self.run(port, events, |session, out, other| {
+                session.handle(apps, interface, request, out, other, routing)
+            });
+        }
+
+        let order = self.port_order();
+        for port in order {
+            if handled != Some(port) {
+                result = result.and(self.poll_pending(apps, port, routing, events));
+            }
+        }
+
+        result?;
+        Ok(order
+            .into_iter()
+            .find(|&port| self.ports[port].transport.is_responded())
+            .map(|port| self.ports[port].interface))
     }
 }
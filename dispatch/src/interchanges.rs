@@ -1,5 +1,5 @@
 pub const SIZE: usize = 3072;
-pub type Data = iso7816::Data<SIZE>;
-pub type Responder<'pipe> = interchange::Responder<'pipe, Data, Data>;
-pub type Requester<'pipe> = interchange::Requester<'pipe, Data, Data>;
-pub type Channel = interchange::Channel<Data, Data>;
+pub type Data<const S: usize = SIZE> = iso7816::Data<S>;
+pub type Responder<'pipe, const S: usize = SIZE> = interchange::Responder<'pipe, Data<S>, Data<S>>;
+pub type Requester<'pipe, const S: usize = SIZE> = interchange::Requester<'pipe, Data<S>, Data<S>>;
+pub type Channel<const S: usize = SIZE> = interchange::Channel<Data<S>, Data<S>>;
@@ -10,17 +10,18 @@ pub use iso7816;
 
 pub mod command {
     pub const SIZE: usize = 7609;
-    pub type Data = iso7816::Data<SIZE>;
+    pub type Data<const S: usize = SIZE> = iso7816::Data<S>;
 }
 
 pub mod response {
     pub const SIZE: usize = 7609;
-    pub type Data = iso7816::Data<SIZE>;
+    pub type Data<const S: usize = SIZE> = iso7816::Data<S>;
 }
 
 // What apps can expect to send and recieve.
-pub type Command = iso7816::Command<{ command::SIZE }>;
-pub type Response = iso7816::Response<{ response::SIZE }>;
+pub type Command<const S: usize = { command::SIZE }> = iso7816::Command<S>;
+pub type Response<const S: usize = { response::SIZE }> = iso7816::Response<S>;
 
 pub mod dispatch;
 pub mod interchanges;
+pub mod transport;
@@ -0,0 +1,163 @@
+//! Transports connect the dispatch to the readers.
+//!
+//! A transport receives commands from a reader and sends the responses back, for example over
+//! CCID, NFC, an APDU tunnel in CTAPHID or a debug UART.  The interchange [`Responder`]s are
+//! transports; other ones implement [`Transport`] directly.
+//!
+//! The dispatch uses its transports as [`Port`]s, which tag a transport with the [`Interface`]
+//! reported to the apps, and give it a priority.
+
+use crate::interchanges::Responder;
+
+use iso7816::Interface;
+
+/// The transport could not send a response
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Error;
+
+/// Link to a reader over which the dispatch receives commands and sends responses.
+///
+/// A transport holds at most one command at a time: it is taken with
+/// [`take_request`](Transport::take_request), and answered with [`send`](Transport::send).
+pub trait Transport {
+    /// Whether a command was received and not taken yet
+    fn has_request(&self) -> bool;
+
+    /// Whether an exchange with the reader is in progress, other than a command waiting to be
+    /// taken: a command is being received, or a response is being prepared or has not been read
+    /// by the reader yet.
+    fn is_busy(&self) -> bool;
+
+    /// Whether a response was sent and has not been read by the reader yet
+    fn is_responded(&self) -> bool;
+
+    /// Take the received command, which stays available until the response is sent
+    fn take_request(&mut self) -> Option<&[u8]>;
+
+    /// Maximum length of a response, including SW1SW2
+    fn capacity(&self) -> usize;
+
+    /// Send the response to the taken command
+    fn send(&mut self, data: &[u8], status: u16) -> Result<(), Error>;
+
+    /// Whether the reader gave up on the taken command.  If its response is deferred, the app is
+    /// told with [`App::cancel`](crate::App::cancel).
+    fn is_canceled(&self) -> bool {
+        false
+    }
+
+    /// Drop the canceled command, so that the transport can receive the next one
+    fn acknowledge_cancel(&mut self) {}
+}
+
+impl<T: Transport + ?Sized> Transport for &mut T {
+    fn has_request(&self) -> bool {
+        T::has_request(self)
+    }
+
+    fn is_busy(&self) -> bool {
+        T::is_busy(self)
+    }
+
+    fn is_responded(&self) -> bool {
+        T::is_responded(self)
+    }
+
+    fn take_request(&mut self) -> Option<&[u8]> {
+        T::take_request(self)
+    }
+
+    fn capacity(&self) -> usize {
+        T::capacity(self)
+    }
+
+    fn send(&mut self, data: &[u8], status: u16) -> Result<(), Error> {
+        T::send(self, data, status)
+    }
+
+    fn is_canceled(&self) -> bool {
+        T::is_canceled(self)
+    }
+
+    fn acknowledge_cancel(&mut self) {
+        T::acknowledge_cancel(self)
+    }
+}
+
+impl<const I: usize> Transport for Responder<'_, I> {
+    fn has_request(&self) -> bool {
+        self.state() == interchange::State::Requested
+    }
+
+    fn is_busy(&self) -> bool {
+        // the correctness of this relies on the properties of interchange - requester can only
+        // send request in the idle state.
+        use interchange::State::*;
+        !matches!(self.state(), Idle | Requested)
+    }
+
+    fn is_responded(&self) -> bool {
+        self.state() == interchange::State::Responded
+    }
+
+    fn take_request(&mut self) -> Option<&[u8]> {
+        // The request is read in place, the response is written over it
+        self.request().ok().map(|request| request.as_slice())
+    }
+
+    fn capacity(&self) -> usize {
+        I
+    }
+
+    /// Write a response in place in the interchange and send it
+    fn send(&mut self, data: &[u8], status: u16) -> Result<(), Error> {
+        debug!("<< {} {:04X}", hex_str!(data, sep:""), status);
+        let response = self.response_mut().map_err(|_| Error)?;
+        response.clear();
+        response.extend_from_slice(data).ok();
+        response.extend_from_slice(&status.to_be_bytes()).ok();
+        self.send_response().map_err(|_| Error)
+    }
+
+    fn is_canceled(&self) -> bool {
+        Responder::is_canceled(self)
+    }
+
+    fn acknowledge_cancel(&mut self) {
+        Responder::acknowledge_cancel(self).ok();
+    }
+}
+
+/// A transport used by the dispatch, with the interface it is reported as to the apps and its
+/// priority.
+///
+/// When several ports have a command, the one with the highest priority is served first, and
+/// ports of equal priority in the order they were given to the dispatch.
+pub struct Port<T> {
+    pub(crate) transport: T,
+    pub(crate) interface: Interface,
+    pub(crate) priority: u8,
+}
+
+impl<T: Transport> Port<T> {
+    pub fn new(transport: T, interface: Interface) -> Self {
+        Self {
+            transport,
+            interface,
+            priority: 0,
+        }
+    }
+
+    pub fn with_priority(mut self, priority: u8) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    pub fn interface(&self) -> Interface {
+        self.interface
+    }
+
+    pub fn priority(&self) -> u8 {
+        self.priority
+    }
+}
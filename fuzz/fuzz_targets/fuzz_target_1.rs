@@ -1,6 +1,6 @@
 #![no_main]
-use apdu_dispatch::app::Result as AppResult;
-use apdu_dispatch::{dispatch::Interface, interchanges, iso7816, App};
+use apdu_dispatch::app::{Context, DeselectReason, Reply, Result as AppResult};
+use apdu_dispatch::{interchanges, iso7816, App};
 use arbitrary::{Arbitrary, Unstructured};
 use heapless::VecView;
 use interchange::Channel;
@@ -57,18 +57,18 @@ impl iso7816::App for FuzzAppImpl {
 impl App for FuzzAppImpl {
     fn select(
         &mut self,
-        _interface: iso7816::Interface,
+        _context: &Context,
         _apdu: apdu_dispatch::app::CommandView<'_>,
         _reply: &mut heapless::VecView<u8>,
     ) -> AppResult {
-        Ok(())
+        Ok(Reply::Ready)
     }
 
-    fn deselect(&mut self) {}
+    fn deselect(&mut self, _channel: u8, _reason: DeselectReason) {}
 
     fn call(
         &mut self,
-        _: Interface,
+        _context: &Context,
         _apdu: apdu_dispatch::app::CommandView<'_>,
         reply: &mut VecView<u8>,
     ) -> AppResult {
@@ -78,7 +78,7 @@ impl App for FuzzAppImpl {
         self.count %= self.responses.len();
         match status {
             Some(s) => Err(*s),
-            None => Ok(()),
+            None => Ok(Reply::Ready),
         }
     }
 }
@@ -116,7 +116,7 @@ fuzz_target!(|input: Input| {
                 .request(interchanges::Data::from_slice(&apdu).unwrap())
                 .expect("could not deposit command");
         }
-        apdu_dispatch.poll(&mut dyn_apps);
+        apdu_dispatch.poll(&mut dyn_apps).unwrap();
 
         if requester {
             contact_requester.take_response().unwrap();